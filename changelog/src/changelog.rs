@@ -7,14 +7,27 @@
 //! Most of the actions are wrapping commands. It would be great to
 //! avoid doing that in future.
 
+use std::cmp::Ordering;
 use std::fmt::{self, Display};
+use std::fs;
 use std::path::PathBuf;
-use std::process::Command;
+use std::process::{Command, ExitStatus, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+
+static NON_INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// When set debchange is run with its standard input closed and does
+/// not query the user.
+pub fn set_non_interactive(non_interactive: bool) {
+    NON_INTERACTIVE.store(non_interactive, AtomicOrdering::Relaxed);
+}
 
 #[derive(Debug)]
 pub enum Error {
     // TODO(sahid): need to handle all the errors
     VersionError(String),
+    ChangeLogError(String),
 }
 
 impl Display for Error {
@@ -22,68 +35,260 @@ impl Display for Error {
         use self::Error::*;
         match self {
             VersionError(s) => write!(f, "unable to parse version: {}", s),
+            ChangeLogError(s) => write!(f, "unable to update changelog: {}", s),
         }
     }
 }
 
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::ChangeLogError(error.to_string())
+    }
+}
+
 // Let's try to be a bit more concise
 pub type Result<T> = std::result::Result<T, Error>;
 
-
 /// Simple data structure to handle some operations arround versioning
 /// [epoch:]<upstream>-[package]
 pub struct Version(Option<u8>, String, String);
 
-impl From<&str> for Version {
-    fn from(value: &str) -> Self {
-        Version(Self::extract_epoch(value).unwrap(),
-                Self::extract_upstream(value).unwrap(),
-                Self::extract_package(value).unwrap())
+impl FromStr for Version {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let upstream = Self::extract_upstream(value)?;
+        if upstream.is_empty() {
+            return Err(Error::VersionError(value.to_string()));
+        }
+        Ok(Version(
+            Self::extract_epoch(value)?,
+            upstream,
+            Self::extract_package(value)?,
+        ))
     }
 }
 
 impl Version {
     fn extract_epoch(value: &str) -> Result<Option<u8>> {
-        let vec: Vec<&str> = value.split(':').collect();
-        match vec[0].parse::<u8>() {
-            Ok(v) => Ok(Some(v)),
-            Err(_) => Ok(None)
+        match value.find(':') {
+            Some(idx) => match value[..idx].parse::<u8>() {
+                Ok(v) => Ok(Some(v)),
+                Err(_) => Err(Error::VersionError(value.to_string())),
+            },
+            None => Ok(None),
         }
     }
 
     fn extract_upstream(value: &str) -> Result<String> {
-        let vec: Vec<&str> = value.split(':').collect();
-        let idx = if Self::extract_epoch(value).is_err() {
-            0
-        } else {
-            1
+        // Only skip the first part when an epoch is actually present.
+        let start = match Self::extract_epoch(value)? {
+            Some(_) => value.find(':').unwrap() + 1,
+            None => 0,
         };
-        match vec[idx].parse::<String>() {
-            Ok(v) => Ok(v),
-            Err(s) => Err(Error::VersionError(s.to_string()))
+        let end = value.rfind('-').unwrap_or(value.len());
+        if end < start {
+            return Err(Error::VersionError(value.to_string()));
         }
+        Ok(value[start..end].to_string())
     }
 
     fn extract_package(value: &str) -> Result<String> {
-        let vec: Vec<&str> = value.split('-').collect();
-        match vec[1].parse::<String>() {
-            Ok(v) => Ok(v),
-            Err(s) => Err(Error::VersionError(s.to_string()))
+        // Native packages or upstream-only strings (e.g. snapshot
+        // versions) do not have any package revision.
+        match value.rfind('-') {
+            Some(idx) => Ok(value[idx + 1..].to_string()),
+            None => Ok(String::new()),
+        }
+    }
+
+    pub fn epoch(&self) -> Option<u8> {
+        self.0
+    }
+
+    pub fn upstream(&self) -> &str {
+        &self.1
+    }
+
+    pub fn package(&self) -> &str {
+        &self.2
+    }
+
+    /// Returns the package revision following this one, the last
+    /// number is incremented (e.g. 0ubuntu3 -> 0ubuntu4).
+    pub fn next_revision(&self) -> Result<String> {
+        let prefix = self.2.trim_end_matches(|c: char| c.is_ascii_digit());
+        match self.2[prefix.len()..].parse::<u64>() {
+            Ok(n) => Ok(format!("{}{}", prefix, n + 1)),
+            Err(_) => Err(Error::VersionError(format!("{}-{}", self.1, self.2))),
         }
     }
 
     pub fn incr_major(&self) -> Result<()> {
         Ok(())
     }
+
+    /// Weight of a character in a non-digit part, following dpkg:
+    /// '~' sorts before everything even the end of a part, letters
+    /// sort before non-letters.
+    fn order(c: Option<&u8>) -> i32 {
+        match c {
+            None => 0,
+            Some(b'~') => -1,
+            Some(c) if c.is_ascii_digit() => 0,
+            Some(c) if c.is_ascii_alphabetic() => i32::from(*c),
+            Some(c) => i32::from(*c) + 256,
+        }
+    }
+
+    /// Compares two upstream or package parts using the Debian
+    /// algorithm, alternating non-digit and digit segments.
+    fn compare_part(a: &str, b: &str) -> Ordering {
+        let (a, b) = (a.as_bytes(), b.as_bytes());
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            while (i < a.len() && !a[i].is_ascii_digit()) || (j < b.len() && !b[j].is_ascii_digit())
+            {
+                let (ac, bc) = (Self::order(a.get(i)), Self::order(b.get(j)));
+                if ac != bc {
+                    return ac.cmp(&bc);
+                }
+                i += 1;
+                j += 1;
+            }
+            while i < a.len() && a[i] == b'0' {
+                i += 1;
+            }
+            while j < b.len() && b[j] == b'0' {
+                j += 1;
+            }
+            let mut first_diff = Ordering::Equal;
+            while i < a.len() && a[i].is_ascii_digit() && j < b.len() && b[j].is_ascii_digit() {
+                if first_diff == Ordering::Equal {
+                    first_diff = a[i].cmp(&b[j]);
+                }
+                i += 1;
+                j += 1;
+            }
+            if i < a.len() && a[i].is_ascii_digit() {
+                return Ordering::Greater;
+            }
+            if j < b.len() && b[j].is_ascii_digit() {
+                return Ordering::Less;
+            }
+            if first_diff != Ordering::Equal {
+                return first_diff;
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .unwrap_or(0)
+            .cmp(&other.0.unwrap_or(0))
+            .then_with(|| Self::compare_part(&self.1, &other.1))
+            .then_with(|| Self::compare_part(&self.2, &other.2))
+    }
 }
 
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Versions like 1.0 and 1.00 are equal for Debian, so equality has to
+// follow the ordering rather than comparing the fields.
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+/// OpenStack release currently developed on master.
+pub const OS_MASTER: &str = "ussuri";
+
+/// Kind of package, it determines the change log messages.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    OpenStack,
+    Regular,
+}
+
+impl From<&str> for Kind {
+    fn from(value: &str) -> Self {
+        match value {
+            "openstack" => Kind::OpenStack,
+            _ => Kind::Regular,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum ChangeLogMessage {
     OSNewUpstreamRelease(String),
+    OSNewUpstreamReleaseWithBug(String, String),
     OSNewUpstreamSnapshot(String),
     OSNewStablePointRelease(String),
     OSNewStablePointReleaseWithBug(String, String),
     NewUpstreamRelease(String),
     NewUpstreamReleaseWithBug(String, String),
+    SecurityFix(String),
+    CVEFix(String, String),
+}
+
+impl ChangeLogMessage {
+    /// Returns the OpenStack release name as used in messages (e.g.
+    /// Stein), master refers to the release in development.
+    pub fn release_name(release: &str) -> String {
+        let release = if release == "master" {
+            OS_MASTER
+        } else {
+            release
+        };
+        let mut c = release.chars();
+        match c.next() {
+            None => String::new(),
+            Some(f) => f.to_uppercase().collect::<String>() + c.as_str(),
+        }
+    }
+
+    /// Selects the message of a new release.
+    ///
+    /// For OpenStack packages, a release on a stable branch is a
+    /// stable point release while a release on master is a new
+    /// upstream release. The bug is referenced as expected by `dist`.
+    pub fn for_release(
+        kind: Kind,
+        dist: Distribution,
+        release: &str,
+        version: &str,
+        bugid: Option<&str>,
+    ) -> ChangeLogMessage {
+        use self::ChangeLogMessage::*;
+        let bug = bugid.map(|bugid| dist.bug_reference(bugid));
+        match (kind, bug) {
+            (Kind::OpenStack, Some(bug)) if release == "master" => {
+                OSNewUpstreamReleaseWithBug(Self::release_name(release), bug)
+            }
+            (Kind::OpenStack, None) if release == "master" => {
+                OSNewUpstreamRelease(Self::release_name(release))
+            }
+            (Kind::OpenStack, Some(bug)) => {
+                OSNewStablePointReleaseWithBug(Self::release_name(release), bug)
+            }
+            (Kind::OpenStack, None) => OSNewStablePointRelease(Self::release_name(release)),
+            (Kind::Regular, Some(bug)) => NewUpstreamReleaseWithBug(version.to_string(), bug),
+            (Kind::Regular, None) => NewUpstreamRelease(version.to_string()),
+        }
+    }
 }
 
 impl Display for ChangeLogMessage {
@@ -91,45 +296,281 @@ impl Display for ChangeLogMessage {
         use self::ChangeLogMessage::*;
         match self {
             OSNewUpstreamRelease(s) => write!(f, "New upstream release for OpenStack {}.", s),
+            OSNewUpstreamReleaseWithBug(s, b) => {
+                write!(f, "New upstream release for OpenStack {} ({}).", s, b)
+            }
             OSNewUpstreamSnapshot(s) => write!(f, "New upstream snapshot for OpenStack {}.", s),
-            OSNewStablePointRelease(s) => write!(f, "New stable point release for OpenStack {}.", s),
+            OSNewStablePointRelease(s) => {
+                write!(f, "New stable point release for OpenStack {}.", s)
+            }
             OSNewStablePointReleaseWithBug(s, b) => {
-                write!(f, "New stable point release for OpenStack {} (LP: #{}).", s, b)
+                write!(f, "New stable point release for OpenStack {} ({}).", s, b)
             }
             NewUpstreamRelease(s) => write!(f, "New upstream release {}.", s),
             NewUpstreamReleaseWithBug(s, b) => {
-                write!(f, "New upstream release {} (LP: #{}).", s, b)
+                write!(f, "New upstream release {} ({}).", s, b)
+            }
+            SecurityFix(s) => write!(f, "SECURITY: fix {}.", s),
+            CVEFix(s, c) => write!(f, "Fix {} (CVE-{}).", s, c.trim_start_matches("CVE-")),
+        }
+    }
+}
+
+/// Distribution targeted by a package, it determines the package
+/// revision of a new release and how bugs are referenced.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Distribution {
+    Ubuntu,
+    Debian,
+}
+
+impl From<&str> for Distribution {
+    fn from(value: &str) -> Self {
+        match value {
+            "debian" => Distribution::Debian,
+            _ => Distribution::Ubuntu,
+        }
+    }
+}
+
+impl Distribution {
+    /// Returns the package revision of a first release.
+    pub fn initial_revision(self) -> &'static str {
+        match self {
+            Distribution::Ubuntu => "0ubuntu1",
+            Distribution::Debian => "1",
+        }
+    }
+
+    /// Returns the reference to `bugid` in a changelog entry, bugs of
+    /// Ubuntu are tracked on Launchpad.
+    pub fn bug_reference(self, bugid: &str) -> String {
+        match self {
+            Distribution::Ubuntu => format!("LP: #{}", bugid),
+            Distribution::Debian => format!("Closes: #{}", bugid),
+        }
+    }
+}
+
+/// How the package revision of a new release is determined.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReleaseMode {
+    /// The revision is reset (e.g. -0ubuntu1).
+    NewUpstream,
+    /// The revision of the current release is continued (e.g.
+    /// -0ubuntu3 -> -0ubuntu4).
+    Merge,
+    Rebuild,
+}
+
+impl From<&str> for ReleaseMode {
+    fn from(value: &str) -> Self {
+        match value {
+            "merge" => ReleaseMode::Merge,
+            "rebuild" => ReleaseMode::Rebuild,
+            _ => ReleaseMode::NewUpstream,
+        }
+    }
+}
+
+/// A single entry of debian/changelog.
+#[derive(Debug, Default, PartialEq)]
+pub struct ChangeLogEntry {
+    pub package: String,
+    pub version: String,
+    pub distribution: String,
+    pub urgency: String,
+    pub changes: Vec<String>,
+    pub maintainer: String,
+    pub date: String,
+}
+
+impl ChangeLogEntry {
+    /// Parses a header line like:
+    /// nova (2:19.0.1-0ubuntu1) eoan; urgency=medium
+    fn parse_header(line: &str) -> Result<ChangeLogEntry> {
+        let err = || Error::ChangeLogError(format!("malformed entry header: {}", line));
+        let open = line.find('(').ok_or_else(err)?;
+        let close = line.find(')').ok_or_else(err)?;
+        let semicolon = line.find(';').ok_or_else(err)?;
+        if !(open < close && close < semicolon) {
+            return Err(err());
+        }
+        let urgency = line[semicolon + 1..]
+            .split(',')
+            .filter_map(|kv| {
+                let mut kv = kv.trim().splitn(2, '=');
+                match (kv.next(), kv.next()) {
+                    (Some("urgency"), Some(v)) => Some(v.to_string()),
+                    _ => None,
+                }
+            })
+            .next()
+            .unwrap_or_default();
+        Ok(ChangeLogEntry {
+            package: line[..open].trim().to_string(),
+            version: line[open + 1..close].trim().to_string(),
+            distribution: line[close + 1..semicolon].trim().to_string(),
+            urgency,
+            ..Default::default()
+        })
+    }
+
+    /// Parses a trailer line like:
+    ///  -- Name <email>  Mon, 17 Jun 2019 10:00:00 +0200
+    fn parse_trailer(&mut self, line: &str) {
+        let line = line.trim_start_matches(" -- ");
+        let mut parts = line.splitn(2, "  ");
+        self.maintainer = parts.next().unwrap_or_default().trim().to_string();
+        self.date = parts.next().unwrap_or_default().trim().to_string();
+    }
+}
+
+/// Parses the content of a debian/changelog, entries are returned
+/// from the most recent to the oldest.
+pub fn parse_entries(content: &str) -> Result<Vec<ChangeLogEntry>> {
+    let mut entries: Vec<ChangeLogEntry> = Vec::new();
+    let mut current: Option<ChangeLogEntry> = None;
+    for line in content.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if !line.starts_with(' ') {
+            if let Some(entry) = current.take() {
+                entries.push(entry);
+            }
+            current = Some(ChangeLogEntry::parse_header(line)?);
+            continue;
+        }
+        let entry = match current.as_mut() {
+            Some(entry) => entry,
+            None => {
+                return Err(Error::ChangeLogError(format!(
+                    "line outside of an entry: {}",
+                    line
+                )))
             }
+        };
+        if line.starts_with(" -- ") {
+            entry.parse_trailer(line);
+        } else if line.trim_start().starts_with("* ") {
+            entry
+                .changes
+                .push(line.trim_start()[2..].trim().to_string());
+        } else if let Some(last) = entry.changes.last_mut() {
+            // Continuation of the previous change.
+            last.push(' ');
+            last.push_str(line.trim());
+        } else {
+            entry.changes.push(line.trim().to_string());
         }
     }
+    if let Some(entry) = current.take() {
+        entries.push(entry);
+    }
+    Ok(entries)
 }
 
 pub struct ChangeLog {
     pub workdir: PathBuf,
+    pub maintainer_name: Option<String>,
+    pub maintainer_email: Option<String>,
+    /// When set the changelog is not modified, commands are only
+    /// printed.
+    pub dry_run: bool,
+    /// When set a new release always creates a new entry, otherwise
+    /// an UNRELEASED top entry is continued.
+    pub new_stanza: bool,
 }
 
 impl ChangeLog {
     pub fn new(workdir: PathBuf) -> ChangeLog {
-        ChangeLog { workdir }
+        ChangeLog {
+            workdir,
+            maintainer_name: None,
+            maintainer_email: None,
+            dry_run: false,
+            new_stanza: false,
+        }
+    }
+
+    /// Sets the maintainer used to sign new entries, otherwise
+    /// debchange relies on DEBFULLNAME and DEBEMAIL.
+    pub fn with_maintainer(mut self, name: &str, email: &str) -> ChangeLog {
+        self.maintainer_name = Some(name.to_string());
+        self.maintainer_email = Some(email.to_string());
+        self
+    }
+
+    fn debchange(&self) -> Command {
+        let mut cmd = Command::new("debchange");
+        cmd.current_dir(&self.workdir);
+        if let Some(name) = &self.maintainer_name {
+            cmd.env("DEBFULLNAME", name);
+        }
+        if let Some(email) = &self.maintainer_email {
+            cmd.env("DEBEMAIL", email);
+        }
+        if NON_INTERACTIVE.load(AtomicOrdering::Relaxed) {
+            cmd.arg("--noquery").stdin(Stdio::null());
+        }
+        cmd
+    }
+
+    fn run(&self, cmd: &mut Command) -> Result<ExitStatus> {
+        Ok(process::run(cmd, self.dry_run)?)
     }
 
+    /// Returns all the entries of debian/changelog.
+    pub fn entries(&self) -> Result<Vec<ChangeLogEntry>> {
+        let mut path = self.workdir.clone();
+        path.push("debian");
+        path.push("changelog");
+        parse_entries(&fs::read_to_string(path)?)
+    }
+
+    /// Returns the version of the top entry, or an empty string when
+    /// the changelog can't be parsed.
     pub fn get_head_full_version(&self) -> String {
-        let o = Command::new("dpkg-parsechangelog")
+        match Command::new("dpkg-parsechangelog")
             .current_dir(&self.workdir)
             .arg("-S")
             .arg("version")
             .output()
-            .expect("unable to import orig");
-        String::from_utf8(o.stdout).unwrap().trim().to_string()
+        {
+            Ok(o) => String::from_utf8_lossy(&o.stdout).trim().to_string(),
+            Err(_) => String::new(),
+        }
+    }
+
+    /// Returns the distribution targeted by the top entry, (e.g.
+    /// eoan, UNRELEASED).
+    pub fn get_head_distribution(&self) -> Option<String> {
+        let o = Command::new("dpkg-parsechangelog")
+            .current_dir(&self.workdir)
+            .arg("-S")
+            .arg("Distribution")
+            .output()
+            .ok()?;
+        if !o.status.success() {
+            return None;
+        }
+        Some(String::from_utf8_lossy(&o.stdout).trim().to_string())
+    }
+
+    /// Indicates whether the top entry has been finalized, that is
+    /// its distribution is known and not UNRELEASED.
+    pub fn is_released(&self) -> bool {
+        match self.get_head_distribution() {
+            Some(dist) => dist != "UNRELEASED",
+            None => false,
+        }
     }
 
     pub fn get_head_epoch(&self) -> Option<u32> {
         let ver = self.get_head_full_version();
         let vec: Vec<&str> = ver.split(':').collect();
-        match vec[0].parse::<u32>() {
-            Ok(v) => Some(v),
-            Err(_) => None,
-        }
+        vec[0].parse::<u32>().ok()
     }
 
     pub fn get_head_version(&self) -> Option<String> {
@@ -144,18 +585,502 @@ impl ChangeLog {
         Some(ver)
     }
 
-    pub fn new_release(&self, version: &str, message: ChangeLogMessage) {
-        // TODO: case without epoch
-        let newversion = match self.get_head_epoch() {
-            Some(epoch) => format!("{}:{}-0ubuntu1", epoch, version),
-            None => format!("{}-0ubuntu1", version),
-        };
-        Command::new("debchange")
-            .current_dir(&self.workdir)
-            .arg("--newversion")
+    /// Returns the full version of a new release of upstream
+    /// `version`, keeping the `epoch` if any.
+    pub fn format_release_version(epoch: Option<u32>, version: &str, dist: Distribution) -> String {
+        match epoch {
+            Some(epoch) => format!("{}:{}-{}", epoch, version, dist.initial_revision()),
+            None => format!("{}-{}", version, dist.initial_revision()),
+        }
+    }
+
+    /// Returns the full version of a new release of upstream
+    /// `version` according to `mode`, merges and rebuilds continue
+    /// the revision of the top entry.
+    pub fn release_version(
+        &self,
+        version: &str,
+        dist: Distribution,
+        mode: ReleaseMode,
+    ) -> Result<String> {
+        let epoch = self.get_head_epoch();
+        if mode == ReleaseMode::NewUpstream {
+            return Ok(Self::format_release_version(epoch, version, dist));
+        }
+        let head = self.get_head_full_version();
+        let revision = head.parse::<Version>()?.next_revision()?;
+        Ok(match epoch {
+            Some(epoch) => format!("{}:{}-{}", epoch, version, revision),
+            None => format!("{}-{}", version, revision),
+        })
+    }
+
+    /// Appends `message` to the top entry without creating a new
+    /// version.
+    pub fn add_entry(&self, message: ChangeLogMessage) -> Result<()> {
+        let o = self.run(&mut self.add_entry_command(&message))?;
+        if !o.success() {
+            return Err(Error::ChangeLogError(message.to_string()));
+        }
+        Ok(())
+    }
+
+    fn add_entry_command(&self, message: &ChangeLogMessage) -> Command {
+        let mut cmd = self.debchange();
+        cmd.arg("--append").arg(message.to_string());
+        cmd
+    }
+
+    pub fn new_release(
+        &self,
+        version: &str,
+        message: ChangeLogMessage,
+        dist: Distribution,
+        mode: ReleaseMode,
+    ) -> Result<()> {
+        if self.continues_release() {
+            return self.continue_release(version, message, dist, mode);
+        }
+        let newversion = self.release_version(version, dist, mode)?;
+        let o = self.run(&mut self.new_release_command(&newversion, &message))?;
+        if !o.success() {
+            return Err(Error::ChangeLogError(newversion));
+        }
+        Ok(())
+    }
+
+    /// Indicates whether a new release continues the UNRELEASED top
+    /// entry rather than adding a new one.
+    fn continues_release(&self) -> bool {
+        !self.new_stanza && self.get_head_distribution().as_deref() == Some("UNRELEASED")
+    }
+
+    fn new_release_command(&self, newversion: &str, message: &ChangeLogMessage) -> Command {
+        let mut cmd = self.debchange();
+        cmd.arg("--newversion")
             .arg(newversion)
-            .arg(message.to_string())
-            .status()
-            .expect("unable to import orig");
+            .arg(message.to_string());
+        cmd
+    }
+
+    /// Appends `message` to the UNRELEASED top entry, which is moved
+    /// to upstream `version`.
+    fn continue_release(
+        &self,
+        version: &str,
+        message: ChangeLogMessage,
+        dist: Distribution,
+        mode: ReleaseMode,
+    ) -> Result<()> {
+        let newversion = self.continued_version(version, dist, mode)?;
+        if newversion != self.get_head_full_version() {
+            self.set_head_version(&newversion)?;
+        }
+        self.add_entry(message)
+    }
+
+    /// Returns the version of the UNRELEASED top entry moved to
+    /// upstream `version`. Merges and rebuilds keep the revision of
+    /// the entry since it has not been released yet.
+    fn continued_version(
+        &self,
+        version: &str,
+        dist: Distribution,
+        mode: ReleaseMode,
+    ) -> Result<String> {
+        if mode == ReleaseMode::NewUpstream {
+            return self.release_version(version, dist, mode);
+        }
+        let head = self.get_head_full_version();
+        let revision = head.parse::<Version>()?.package().to_string();
+        Ok(match self.get_head_epoch() {
+            Some(epoch) => format!("{}:{}-{}", epoch, version, revision),
+            None => format!("{}-{}", version, revision),
+        })
+    }
+
+    /// Replaces the version of the top entry by `version`.
+    fn set_head_version(&self, version: &str) -> Result<()> {
+        let path = self.workdir.join("debian").join("changelog");
+        if self.dry_run {
+            println!(
+                "[dry-run] set version of the top entry of {} to {}",
+                path.display(),
+                version
+            );
+            return Ok(());
+        }
+        let content = fs::read_to_string(&path)?;
+        let (header, rest) = content.split_at(content.find('\n').unwrap_or(content.len()));
+        let header = match (header.find('('), header.find(')')) {
+            (Some(start), Some(end)) if start < end => {
+                format!("{}({}{}", &header[..start], version, &header[end..])
+            }
+            _ => return Err(Error::ChangeLogError(version.to_string())),
+        };
+        fs::write(&path, format!("{}{}", header, rest))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(value: &str) -> Version {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn version_without_package_revision() {
+        let v = version("19.0.1");
+        assert_eq!(v.upstream(), "19.0.1");
+        assert_eq!(v.package(), "");
+
+        let v = version("19.0.1~b1~git2019061715.86823b5c");
+        assert_eq!(v.upstream(), "19.0.1~b1~git2019061715.86823b5c");
+        assert_eq!(v.package(), "");
+    }
+
+    #[test]
+    fn invalid_versions() {
+        assert!("".parse::<Version>().is_err());
+        assert!("1:".parse::<Version>().is_err());
+        assert!("a:1.0-1".parse::<Version>().is_err());
+        assert!("-0ubuntu1".parse::<Version>().is_err());
+    }
+
+    #[test]
+    fn extract_epoch() {
+        assert_eq!(
+            Version::extract_epoch("2:19.0.1-0ubuntu1").unwrap(),
+            Some(2)
+        );
+        assert_eq!(Version::extract_epoch("19.0.1-0ubuntu1").unwrap(), None);
+        assert!(Version::extract_epoch("a:19.0.1-0ubuntu1").is_err());
+    }
+
+    #[test]
+    fn extract_upstream() {
+        assert_eq!(
+            Version::extract_upstream("2:19.0.1-0ubuntu1").unwrap(),
+            "19.0.1"
+        );
+        assert_eq!(
+            Version::extract_upstream("19.0.1-0ubuntu1").unwrap(),
+            "19.0.1"
+        );
+        assert_eq!(
+            Version::extract_upstream("1:2.0-1-0ubuntu1").unwrap(),
+            "2.0-1"
+        );
+    }
+
+    #[test]
+    fn version_ordering() {
+        assert!(version("19.0.1~b1") < version("19.0.1"));
+        assert!(version("19.0.1") < version("19.0.2"));
+        assert!(version("19.0.1~b1") < version("19.0.1~b2"));
+        assert!(version("19.0.1-0ubuntu1") < version("19.0.1-0ubuntu2"));
+        assert!(version("1:1.0") > version("2.0"));
+        assert!(version("1.0") == version("1.00"));
+    }
+
+    #[test]
+    fn format_release_version() {
+        assert_eq!(
+            ChangeLog::format_release_version(None, "19.0.1", Distribution::Ubuntu),
+            "19.0.1-0ubuntu1"
+        );
+        assert_eq!(
+            ChangeLog::format_release_version(None, "19.0.1", Distribution::Debian),
+            "19.0.1-1"
+        );
+        assert_eq!(
+            ChangeLog::format_release_version(Some(2), "19.0.1", Distribution::Ubuntu),
+            "2:19.0.1-0ubuntu1"
+        );
+    }
+
+    #[test]
+    fn security_messages() {
+        assert_eq!(
+            ChangeLogMessage::SecurityFix("arbitrary file read in console".to_string()).to_string(),
+            "SECURITY: fix arbitrary file read in console."
+        );
+        assert_eq!(
+            ChangeLogMessage::CVEFix(
+                "arbitrary file read".to_string(),
+                "CVE-2019-12345".to_string()
+            )
+            .to_string(),
+            "Fix arbitrary file read (CVE-2019-12345)."
+        );
+    }
+
+    #[test]
+    fn message_for_release() {
+        let msg = |kind, release, bugid| {
+            ChangeLogMessage::for_release(kind, Distribution::Ubuntu, release, "19.0.1", bugid)
+                .to_string()
+        };
+        assert_eq!(
+            msg(Kind::OpenStack, "master", None),
+            format!(
+                "New upstream release for OpenStack {}.",
+                ChangeLogMessage::release_name(OS_MASTER)
+            )
+        );
+        assert_eq!(
+            msg(Kind::OpenStack, "master", Some("1867654")),
+            format!(
+                "New upstream release for OpenStack {} (LP: #1867654).",
+                ChangeLogMessage::release_name(OS_MASTER)
+            )
+        );
+        assert_eq!(
+            msg(Kind::OpenStack, "stein", None),
+            "New stable point release for OpenStack Stein."
+        );
+        assert_eq!(
+            msg(Kind::OpenStack, "stein", Some("1867654")),
+            "New stable point release for OpenStack Stein (LP: #1867654)."
+        );
+        assert_eq!(
+            msg(Kind::Regular, "master", None),
+            "New upstream release 19.0.1."
+        );
+        assert_eq!(
+            msg(Kind::Regular, "stein", Some("1867654")),
+            "New upstream release 19.0.1 (LP: #1867654)."
+        );
+        assert_eq!(
+            ChangeLogMessage::for_release(
+                Kind::Regular,
+                Distribution::Debian,
+                "master",
+                "19.0.1",
+                Some("950123")
+            )
+            .to_string(),
+            "New upstream release 19.0.1 (Closes: #950123)."
+        );
+    }
+
+    #[test]
+    fn debchange_non_interactive() {
+        let chg = ChangeLog::new(PathBuf::from("/tmp/nova"));
+        let has_noquery = |cmd: &Command| cmd.get_args().any(|a| a == "--noquery");
+        assert!(!has_noquery(&chg.debchange()));
+        set_non_interactive(true);
+        let cmd = chg.debchange();
+        set_non_interactive(false);
+        assert!(has_noquery(&cmd));
+    }
+
+    /// Returns a `ChangeLog` of a temporary checkout whose
+    /// debian/changelog is `content`.
+    fn temp_changelog(name: &str, content: &str) -> ChangeLog {
+        let workdir =
+            std::env::temp_dir().join(format!("uosp-changelog-{}-{}", name, std::process::id()));
+        fs::create_dir_all(workdir.join("debian")).unwrap();
+        fs::write(workdir.join("debian/changelog"), content).unwrap();
+        ChangeLog::new(workdir)
+    }
+
+    fn entry(version: &str, distribution: &str) -> String {
+        format!(
+            "nova ({}) {}; urgency=medium\n\n  \
+             * New upstream release.\n\n \
+             -- Sahid Orentino Ferdjaoui <sahid.ferdjaoui@canonical.com>  \
+             Mon, 17 Jun 2019 15:30:00 +0000\n\n",
+            version, distribution
+        )
+    }
+
+    #[test]
+    fn is_released() {
+        let chg = temp_changelog("released", &entry("2:19.0.1-0ubuntu1", "eoan"));
+        assert!(chg.is_released());
+        assert_eq!(chg.get_head_distribution().as_deref(), Some("eoan"));
+        fs::write(
+            chg.workdir.join("debian/changelog"),
+            entry("2:19.0.2-0ubuntu1", "UNRELEASED") + &entry("2:19.0.1-0ubuntu1", "eoan"),
+        )
+        .unwrap();
+        assert!(!chg.is_released());
+        fs::remove_dir_all(&chg.workdir).unwrap();
+
+        // Nothing is released without a changelog.
+        let chg = ChangeLog::new(PathBuf::from("/nonexistent"));
+        assert!(!chg.is_released());
+    }
+
+    #[test]
+    fn parse_entries_fixture() {
+        let entries = parse_entries(include_str!("../testdata/changelog")).unwrap();
+        assert_eq!(entries.len(), 3);
+
+        let top = &entries[0];
+        assert_eq!(top.package, "nova");
+        assert_eq!(top.version, "2:20.0.0~b1-0ubuntu1");
+        assert_eq!(top.distribution, "UNRELEASED");
+        assert_eq!(top.urgency, "medium");
+        assert_eq!(
+            top.changes,
+            [
+                "New upstream snapshot for OpenStack Ussuri.",
+                "d/control: Align (Build-)Depends with upstream, dropping \
+                 python3-mox3 which is not used anymore.",
+            ]
+        );
+        assert_eq!(
+            top.maintainer,
+            "Sahid Orentino Ferdjaoui <sahid.ferdjaoui@canonical.com>"
+        );
+        assert_eq!(top.date, "Tue, 22 Oct 2019 10:12:31 +0200");
+
+        assert_eq!(entries[1].version, "2:20.0.0-0ubuntu1");
+        assert_eq!(entries[1].urgency, "high");
+        assert_eq!(
+            entries[1].maintainer,
+            "Corey Bryant <corey.bryant@canonical.com>"
+        );
+
+        let last = &entries[2];
+        assert_eq!(last.distribution, "disco-security");
+        assert_eq!(last.urgency, "low");
+        assert_eq!(
+            last.changes,
+            [
+                "[ Sahid Orentino Ferdjaoui ]",
+                "New stable point release for OpenStack Stein (LP: #1867654).",
+            ]
+        );
+        assert_eq!(last.date, "Mon, 17 Jun 2019 15:30:00 +0100");
+    }
+
+    #[test]
+    fn parse_entries_malformed() {
+        assert!(parse_entries("  * orphan change\n").is_err());
+        assert!(parse_entries("nova 2:19.0.1-0ubuntu1 eoan urgency=medium\n").is_err());
+        assert!(parse_entries("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn add_entry_command() {
+        let chg = ChangeLog::new(PathBuf::from("/tmp/nova"))
+            .with_maintainer("Sahid Orentino Ferdjaoui", "sahid.ferdjaoui@canonical.com");
+        let cmd = chg.add_entry_command(&ChangeLogMessage::SecurityFix(
+            "arbitrary file read in console".into(),
+        ));
+        assert_eq!(cmd.get_program(), "debchange");
+        assert_eq!(
+            cmd.get_current_dir(),
+            Some(PathBuf::from("/tmp/nova").as_path())
+        );
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(
+            args,
+            ["--append", "SECURITY: fix arbitrary file read in console."]
+        );
+        let envs: Vec<_> = cmd.get_envs().collect();
+        assert!(envs.contains(&(
+            "DEBFULLNAME".as_ref(),
+            Some("Sahid Orentino Ferdjaoui".as_ref())
+        )));
+        assert!(envs.contains(&(
+            "DEBEMAIL".as_ref(),
+            Some("sahid.ferdjaoui@canonical.com".as_ref())
+        )));
+    }
+
+    #[test]
+    fn next_revision() {
+        assert_eq!(
+            version("19.0.1-0ubuntu1").next_revision().unwrap(),
+            "0ubuntu2"
+        );
+        assert_eq!(
+            version("19.0.1-0ubuntu9").next_revision().unwrap(),
+            "0ubuntu10"
+        );
+        assert_eq!(version("2:19.0.1-3").next_revision().unwrap(), "4");
+        assert_eq!(
+            version("19.0.1-0ubuntu1~cloud0").next_revision().unwrap(),
+            "0ubuntu1~cloud1"
+        );
+        assert!(version("19.0.1-0ubuntu").next_revision().is_err());
+    }
+
+    #[test]
+    fn release_version() {
+        let chg = temp_changelog("release-version", &entry("2:19.0.0-0ubuntu3", "eoan"));
+        let release = |mode| chg.release_version("19.0.1", Distribution::Ubuntu, mode);
+        let new_upstream = release(ReleaseMode::NewUpstream);
+        let merge = release(ReleaseMode::Merge);
+        let rebuild = release(ReleaseMode::Rebuild);
+        let debian = chg.release_version("19.0.1", Distribution::Debian, ReleaseMode::NewUpstream);
+        fs::remove_dir_all(&chg.workdir).unwrap();
+        assert_eq!(new_upstream.unwrap(), "2:19.0.1-0ubuntu1");
+        assert_eq!(merge.unwrap(), "2:19.0.1-0ubuntu4");
+        assert_eq!(rebuild.unwrap(), "2:19.0.1-0ubuntu4");
+        assert_eq!(debian.unwrap(), "2:19.0.1-1");
+
+        let chg = temp_changelog("release-version-no-epoch", &entry("19.0.0-2", "unstable"));
+        let merge = chg.release_version("19.0.1", Distribution::Debian, ReleaseMode::Merge);
+        fs::remove_dir_all(&chg.workdir).unwrap();
+        assert_eq!(merge.unwrap(), "19.0.1-3");
+    }
+
+    #[test]
+    fn new_release_command() {
+        let chg = ChangeLog::new(PathBuf::from("/tmp/nova"));
+        let message = ChangeLogMessage::OSNewUpstreamRelease("Stein".to_string());
+        let cmd = chg.new_release_command("2:19.0.1-0ubuntu1", &message);
+        assert_eq!(cmd.get_program(), "debchange");
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(
+            args,
+            [
+                "--newversion",
+                "2:19.0.1-0ubuntu1",
+                "New upstream release for OpenStack Stein."
+            ]
+        );
+    }
+
+    #[test]
+    fn continue_unreleased_entry() {
+        let content =
+            entry("2:19.0.1~b1-0ubuntu2", "UNRELEASED") + &entry("2:19.0.0-0ubuntu1", "eoan");
+        let mut chg = temp_changelog("continue-release", &content);
+        let continues = chg.continues_release();
+        let new_upstream =
+            chg.continued_version("19.0.1", Distribution::Ubuntu, ReleaseMode::NewUpstream);
+        let merge = chg.continued_version("19.0.1", Distribution::Ubuntu, ReleaseMode::Merge);
+        let moved = chg.set_head_version("2:19.0.1-0ubuntu1");
+        let head = chg.get_head_full_version();
+        let entries = chg.entries();
+        chg.new_stanza = true;
+        let new_stanza = chg.continues_release();
+        fs::remove_dir_all(&chg.workdir).unwrap();
+
+        assert!(continues);
+        assert!(!new_stanza);
+        assert_eq!(new_upstream.unwrap(), "2:19.0.1-0ubuntu1");
+        assert_eq!(merge.unwrap(), "2:19.0.1-0ubuntu2");
+        moved.unwrap();
+        assert_eq!(head, "2:19.0.1-0ubuntu1");
+        let entries = entries.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].distribution, "UNRELEASED");
+        assert_eq!(entries[1].version, "2:19.0.0-0ubuntu1");
+
+        // A released top entry is never continued.
+        let chg = temp_changelog("new-release", &entry("2:19.0.0-0ubuntu1", "eoan"));
+        let continues = chg.continues_release();
+        fs::remove_dir_all(&chg.workdir).unwrap();
+        assert!(!continues);
     }
 }
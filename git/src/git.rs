@@ -8,19 +8,44 @@
 //! to avoid doing that in future.
 
 use std::fmt::{self, Display};
-use std::path::PathBuf;
-use std::process::Command;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use process::{prefix_output, run, run_output, run_with_input};
 
 #[derive(Debug)]
 pub enum Error {
     // TODO(sahid): need to handle all the errors
     CloneError(String),
+    InitError(String),
     CheckoutError(String),
+    RenameError(String),
+    BranchExistsError(String),
     PullError(),
+    FetchError(String),
     ShowError(),
     PushError(String),
+    TrackError(String),
+    CommitError(String),
+    LogError(String),
+    TagError(String),
+    RemoteExistsError(String),
+    RemoteError(String),
+    ResetError(String),
+    UpdateRefError(String),
+    MergeError(String),
+    MergeConflict(String, String),
+    DiffError(String),
+    WorktreeError(String),
     HashError(),
+    GcError(),
     ApplyError(),
+    FsckError(String, String),
+    UrlError(String),
+    MirrorOriginError(String),
+    TimeoutError(String),
     Fatal(String),
 }
 
@@ -29,39 +54,156 @@ impl Display for Error {
         use self::Error::*;
         match self {
             CloneError(s) => write!(f, "unable to git clone project {}", s),
+            InitError(s) => write!(f, "unable to git init project {}", s),
             CheckoutError(s) => write!(f, "unable to checkout branch {}", s),
+            RenameError(s) => write!(f, "unable to rename branch {}", s),
+            BranchExistsError(s) => write!(f, "branch {} already exists", s),
             PullError() => write!(f, "unable to pull last changes"),
+            FetchError(s) => write!(f, "unable to fetch from {}", s),
             ShowError() => write!(f, "unable to show last commit"),
             HashError() => write!(f, "unable to generate hash based on last commit"),
+            GcError() => write!(f, "unable to garbage collect repository"),
             PushError(s) => write!(f, "unable to push changes to {}", s),
+            TrackError(s) => write!(f, "unable to set upstream tracking for branch {}", s),
+            CommitError(s) => write!(f, "unable to commit changes, {}", s),
+            LogError(s) => write!(f, "unable to log commits of {}", s),
+            TagError(s) => write!(f, "unable to tag {}", s),
+            RemoteExistsError(s) => write!(f, "remote {} already exists", s),
+            RemoteError(s) => write!(f, "unable to configure remote {}", s),
+            ResetError(s) => write!(f, "unable to reset to {}", s),
+            UpdateRefError(s) => write!(f, "unable to update reference {}", s),
+            MergeError(s) => write!(f, "unable to merge {}", s),
+            MergeConflict(s, c) => write!(
+                f,
+                "merging {} conflicts in {}, please resolve them manually",
+                s, c
+            ),
+            DiffError(s) => write!(f, "unable to diff {}", s),
+            WorktreeError(s) => write!(f, "unable to {} worktrees", s),
             ApplyError() => write!(f, "unable to apply patch"),
+            UrlError(s) => write!(f, "invalid git URL {}", s),
+            MirrorOriginError(s) => write!(f, "refusing to mirror to origin {}", s),
+            FsckError(s, e) => write!(f, "repository {} is corrupted, {}", s, e),
+            TimeoutError(s) => write!(f, "command timed out: {}", s),
             Fatal(s) => write!(f, "unexpected error {}", s),
         }
     }
 }
 
+impl std::error::Error for Error {}
+
 impl From<std::io::Error> for Error {
     fn from(error: std::io::Error) -> Self {
-        Error::Fatal(error.to_string())
+        match error.kind() {
+            io::ErrorKind::TimedOut => Error::TimeoutError(error.to_string()),
+            _ => Error::Fatal(error.to_string()),
+        }
     }
 }
 
 // Let's try to be a bit more concise
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Hash of the empty tree, known by git without being stored, to
+/// diff against when there is no previous commit.
+pub const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+static ISOLATED: AtomicBool = AtomicBool::new(false);
+
+/// When set the git commands run ignore the system and global
+/// configurations (hooks included) of the user, the identity has to
+/// be provided by the GIT_AUTHOR_* and GIT_COMMITTER_* variables.
+pub fn set_isolated(isolated: bool) {
+    ISOLATED.store(isolated, Ordering::Relaxed);
+}
+
+/// Returns the environment used to isolate git commands, `HOME` is
+/// pointed to an empty directory.
+pub fn isolation_env() -> Vec<(&'static str, String)> {
+    let home = std::env::temp_dir().join("uosp-isolated");
+    vec![
+        ("GIT_CONFIG_NOSYSTEM", "1".to_string()),
+        ("GIT_CONFIG_GLOBAL", "/dev/null".to_string()),
+        ("HOME", home.display().to_string()),
+        ("GIT_CONFIG_COUNT", "1".to_string()),
+        ("GIT_CONFIG_KEY_0", "core.hooksPath".to_string()),
+        ("GIT_CONFIG_VALUE_0", "/dev/null".to_string()),
+    ]
+}
+
+/// Configures `cmd` according to the isolation mode, to be used for
+/// every command running git under the hood (gbp, debcommit...).
+pub fn isolate(cmd: &mut Command) -> &mut Command {
+    if ISOLATED.load(Ordering::Relaxed) {
+        cmd.envs(isolation_env());
+    }
+    cmd
+}
+
+/// Indicates whether `url` is usable as a git URL, either with a
+/// scheme (https://, ssh://...), scp-like (git@host:path) or an
+/// absolute path.
+pub fn is_git_url(url: &str) -> bool {
+    if let Some((scheme, rest)) = url.split_once("://") {
+        return !scheme.is_empty() && !rest.is_empty();
+    }
+    if url.starts_with('/') {
+        return true;
+    }
+    match url.split_once(':') {
+        Some((host, path)) => !host.is_empty() && !host.contains('/') && !path.is_empty(),
+        None => false,
+    }
+}
+
+/// How git is invoked, `global_args` are passed before the
+/// subcommand (e.g. -c user.email=...).
+#[derive(Debug, Clone)]
+pub struct GitOptions {
+    pub git_bin: String,
+    pub global_args: Vec<String>,
+}
+
+impl Default for GitOptions {
+    fn default() -> Self {
+        GitOptions {
+            git_bin: "git".to_string(),
+            global_args: Vec::new(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Git {
     pub workdir: PathBuf,
+    /// When set commands are only printed.
+    pub dry_run: bool,
+    pub options: GitOptions,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum GitCloneUrl {
     OpenStackUpstream(String),
     UbuntuServerDev(String),
+    /// The repository of source package `name` in the launchpad
+    /// account `owner`.
+    Launchpad {
+        owner: String,
+        name: String,
+    },
     GitHub(String),
+    GitLab(String),
     Salsa(String),
+    /// A self-hosted forge, e.g. git.example.org and group/project.
+    Host {
+        host: String,
+        path: String,
+    },
     Plain(String),
     VCSGit,
+    /// Same repository as the inner URL but accessed over SSH (e.g.
+    /// git@salsa.debian.org:openstack-team/nova.git).
+    Ssh(Box<GitCloneUrl>),
 }
 
 impl Display for GitCloneUrl {
@@ -74,34 +216,118 @@ impl Display for GitCloneUrl {
                 "https://git.launchpad.net/~ubuntu-server-dev/ubuntu/+source/{}",
                 s
             ),
+            Launchpad { owner, name } => write!(
+                f,
+                "https://git.launchpad.net/~{}/ubuntu/+source/{}",
+                owner, name
+            ),
             GitHub(s) => write!(f, "https://github.com/{}.git", s),
+            GitLab(s) => write!(f, "https://gitlab.com/{}.git", s),
             Salsa(s) => write!(f, "https://salsa.debian.org/{}.git", s),
+            Host { host, path } => write!(f, "https://{}/{}.git", host, path),
             Plain(s) => write!(f, "{}", s),
             VCSGit => Ok(()),
+            Ssh(url) => match url.as_ref() {
+                OpenStackUpstream(s) => write!(f, "git@github.com:openstack/{}.git", s),
+                UbuntuServerDev(s) => write!(
+                    f,
+                    "git+ssh://git.launchpad.net/~ubuntu-server-dev/ubuntu/+source/{}",
+                    s
+                ),
+                Launchpad { owner, name } => write!(
+                    f,
+                    "git+ssh://{}@git.launchpad.net/~{}/ubuntu/+source/{}",
+                    owner, owner, name
+                ),
+                GitHub(s) => write!(f, "git@github.com:{}.git", s),
+                GitLab(s) => write!(f, "git@gitlab.com:{}.git", s),
+                Salsa(s) => write!(f, "git@salsa.debian.org:{}.git", s),
+                Host { host, path } => write!(f, "git@{}:{}.git", host, path),
+                // Already in their final form.
+                url => write!(f, "{}", url),
+            },
+        }
+    }
+}
+
+impl GitCloneUrl {
+    /// Returns the SSH form of the URL, needed to push or to access
+    /// private repositories.
+    pub fn ssh(self) -> GitCloneUrl {
+        match self {
+            GitCloneUrl::Ssh(_) => self,
+            url => GitCloneUrl::Ssh(Box::new(url)),
+        }
+    }
+
+    /// Parses a clone URL specified by the user, either a URL, vcsgit
+    /// or `<forge>:<path>` where forge is one of github, gitlab,
+    /// salsa, openstack or a hostname (e.g. gitlab:group/proj,
+    /// git.example.org:group/proj).
+    pub fn from_spec(spec: &str) -> Option<GitCloneUrl> {
+        if spec == "vcsgit" {
+            return Some(GitCloneUrl::VCSGit);
+        }
+        if spec.contains("://") || spec.starts_with("git@") {
+            return Some(GitCloneUrl::Plain(spec.to_string()));
+        }
+        let (forge, path) = spec.split_once(':')?;
+        let path = path.trim_matches('/').trim_end_matches(".git");
+        if path.is_empty() {
+            return None;
+        }
+        let path = path.to_string();
+        match forge {
+            "github" => Some(GitCloneUrl::GitHub(path)),
+            "gitlab" => Some(GitCloneUrl::GitLab(path)),
+            "salsa" => Some(GitCloneUrl::Salsa(path)),
+            "openstack" => Some(GitCloneUrl::OpenStackUpstream(path)),
+            host if host.contains('.') => Some(GitCloneUrl::Host {
+                host: host.to_string(),
+                path,
+            }),
+            _ => None,
         }
     }
 }
 
 impl Git {
-    pub fn new(name: &str, rootdir: PathBuf, url: GitCloneUrl) -> Result<Git> {
+    pub fn new(name: &str, rootdir: PathBuf, url: GitCloneUrl, dry_run: bool) -> Result<Git> {
+        Self::with_options(name, rootdir, url, dry_run, GitOptions::default())
+    }
+
+    /// Same as `new` but git is invoked according to `options`.
+    pub fn with_options(
+        name: &str,
+        rootdir: PathBuf,
+        url: GitCloneUrl,
+        dry_run: bool,
+        options: GitOptions,
+    ) -> Result<Git> {
         let mut workdir = rootdir.clone();
         workdir.push(name);
-        let git = Git { workdir };
+        let git = Git {
+            workdir,
+            dry_run,
+            options,
+        };
         if !git.exists() {
-            Command::new("mkdir").arg("-p").arg(&rootdir).status()?;
+            git.run(Command::new("mkdir").arg("-p").arg(&rootdir))?;
 
             let o = if url == GitCloneUrl::VCSGit {
-                Command::new("gbp")
-                    .current_dir(&rootdir)
-                    .arg("clone")
-                    .arg(format!("vcsgit:{}", name))
-                    .status()?
+                git.run(
+                    isolate(&mut Command::new("gbp"))
+                        .current_dir(&rootdir)
+                        .arg("clone")
+                        .arg(format!("vcsgit:{}", name)),
+                )?
             } else {
-                Command::new("git")
-                    .current_dir(&rootdir)
-                    .arg("clone")
-                    .arg(url.to_string())
-                    .status()?
+                git.run(
+                    git.command()
+                        .current_dir(&rootdir)
+                        .arg("clone")
+                        .arg(url.to_string()),
+                )?
             };
             if !o.success() {
                 return Err(Error::CloneError(name.to_string()));
@@ -110,103 +336,1423 @@ impl Git {
         Ok(git)
     }
 
+    /// Returns a `Git` for a brand-new repository initialized in
+    /// `rootdir/name` with `master` as initial branch.
+    pub fn init(rootdir: PathBuf, name: &str) -> Result<Git> {
+        let mut workdir = rootdir;
+        workdir.push(name);
+        std::fs::create_dir_all(&workdir)?;
+        let git = Git::open(workdir);
+        let o = git.output(git.command().arg("init"))?;
+        if !o.status.success() {
+            return Err(Error::InitError(name.to_string()));
+        }
+        // Using symbolic-ref rather than --initial-branch to support
+        // older versions of git.
+        let o = git.output(
+            git.command()
+                .arg("symbolic-ref")
+                .arg("HEAD")
+                .arg("refs/heads/master"),
+        )?;
+        if !o.status.success() {
+            return Err(Error::InitError(name.to_string()));
+        }
+        Ok(git)
+    }
+
+    /// Returns a `Git` for an already existing repository located at
+    /// `workdir`.
+    pub fn open(workdir: PathBuf) -> Git {
+        Git {
+            workdir,
+            dry_run: false,
+            options: GitOptions::default(),
+        }
+    }
+
+    /// Returns a git command run in `workdir`, every git invocation
+    /// goes through it.
+    fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.options.git_bin);
+        cmd.current_dir(&self.workdir)
+            .args(&self.options.global_args);
+        isolate(&mut cmd);
+        cmd
+    }
+
+    fn run(&self, cmd: &mut Command) -> Result<ExitStatus> {
+        match self.workdir.file_name() {
+            Some(name) if prefix_output() && !self.dry_run => {
+                Ok(process::run_with_prefix(cmd, &name.to_string_lossy())?)
+            }
+            _ => Ok(run(cmd, self.dry_run)?),
+        }
+    }
+
+    fn output(&self, cmd: &mut Command) -> Result<Output> {
+        Ok(run_output(cmd, self.dry_run)?)
+    }
+
+    /// Same as `output` for read-only commands, which are run even in
+    /// dry-run mode unless the repository has not been cloned.
+    fn query(&self, cmd: &mut Command) -> Result<Output> {
+        Ok(run_output(cmd, self.dry_run && !self.exists())?)
+    }
+
     pub fn exists(&self) -> bool {
         self.workdir.exists()
     }
 
+    /// Indicates whether `workdir` is inside a git work tree.
+    pub fn is_git_repo(&self) -> bool {
+        if !self.exists() {
+            return false;
+        }
+        match self.query(self.command().arg("rev-parse").arg("--is-inside-work-tree")) {
+            Ok(o) => o.status.success() && String::from_utf8_lossy(&o.stdout).trim() == "true",
+            Err(_) => false,
+        }
+    }
+
+    /// Checks out `branch`, when only origin/`branch` exists a local
+    /// branch tracking it is created.
     pub fn checkout(&self, branch: &str) -> Result<()> {
-        let o = Command::new("git")
-            .current_dir(&self.workdir)
-            .arg("checkout")
-            .arg(branch)
-            .status()?;
+        let mut cmd = self.command();
+        cmd.arg("checkout");
+        if !self.branch_exists(branch)? && self.remote_branch_exists(branch)? {
+            cmd.arg("-b")
+                .arg(branch)
+                .arg("--track")
+                .arg(format!("origin/{}", branch));
+        } else {
+            cmd.arg(branch);
+        }
+        let o = self.run(&mut cmd)?;
         if !o.success() {
             return Err(Error::CheckoutError(branch.to_string()));
         }
         Ok(())
     }
 
+    /// Checks out `rev` (e.g. a commit or a tag) with a detached HEAD.
+    pub fn checkout_detached(&self, rev: &str) -> Result<()> {
+        let o = self.run(self.command().arg("checkout").arg("--detach").arg(rev))?;
+        if !o.success() {
+            return Err(Error::CheckoutError(rev.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Returns the name of the branch checked out, fails if HEAD is
+    /// detached.
+    pub fn current_branch(&self) -> Result<String> {
+        let o = self.query(
+            self.command()
+                .arg("rev-parse")
+                .arg("--abbrev-ref")
+                .arg("HEAD"),
+        )?;
+        if !o.status.success() {
+            return Err(Error::Fatal("unable to resolve HEAD".to_string()));
+        }
+        let branch = String::from_utf8_lossy(&o.stdout).trim().to_string();
+        if branch == "HEAD" {
+            return Err(Error::Fatal("HEAD is detached".to_string()));
+        }
+        Ok(branch)
+    }
+
+    /// Creates and checks out branch `name` from `from`, or from
+    /// HEAD when not set.
+    pub fn create_branch(&self, name: &str, from: Option<&str>) -> Result<()> {
+        if self.branch_exists(name)? {
+            return Err(Error::BranchExistsError(name.to_string()));
+        }
+        let mut cmd = self.command();
+        cmd.arg("checkout").arg("-b").arg(name);
+        if let Some(from) = from {
+            cmd.arg(from);
+        }
+        if !self.run(&mut cmd)?.success() {
+            return Err(Error::CheckoutError(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Renames branch `old` to `new`, fails if `new` already exists
+    /// rather than overwriting it.
+    pub fn rename_branch(&self, old: &str, new: &str) -> Result<()> {
+        if self.branch_exists(new)? {
+            return Err(Error::BranchExistsError(new.to_string()));
+        }
+        let o = self.run(self.command().arg("branch").arg("-m").arg(old).arg(new))?;
+        if !o.success() {
+            return Err(Error::RenameError(old.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Sets `remote_branch` (e.g. origin/master) as the upstream
+    /// tracked by the local `branch`.
+    pub fn set_upstream(&self, branch: &str, remote_branch: &str) -> Result<()> {
+        let o = self.run(
+            self.command()
+                .arg("branch")
+                .arg(format!("--set-upstream-to={}", remote_branch))
+                .arg(branch),
+        )?;
+        if !o.success() {
+            return Err(Error::TrackError(branch.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Resets the current branch and the working tree to `to`, the
+    /// local changes are lost.
+    pub fn reset_hard(&self, to: &str) -> Result<()> {
+        let o = self.run(self.command().arg("reset").arg("--hard").arg(to))?;
+        if !o.success() {
+            return Err(Error::ResetError(to.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Points `refname` to `hash`, the working tree is left untouched
+    /// so it should not be the branch checked out.
+    pub fn update_ref(&self, refname: &str, hash: &str) -> Result<()> {
+        let o = self.run(self.command().arg("update-ref").arg(refname).arg(hash))?;
+        if !o.success() {
+            return Err(Error::UpdateRefError(refname.to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn delete_ref(&self, refname: &str) -> Result<()> {
+        let o = self.run(self.command().arg("update-ref").arg("-d").arg(refname))?;
+        if !o.success() {
+            return Err(Error::UpdateRefError(refname.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Merges `branch` in the current branch, using `strategy` when
+    /// set (e.g. ours). Fails with `MergeConflict` listing the files
+    /// to resolve.
+    pub fn merge(&self, branch: &str, strategy: Option<&str>) -> Result<()> {
+        if self
+            .run(&mut self.merge_command(branch, strategy))?
+            .success()
+        {
+            return Ok(());
+        }
+        let o = self.output(
+            self.command()
+                .arg("diff")
+                .arg("--name-only")
+                .arg("--diff-filter=U"),
+        )?;
+        let conflicts: Vec<String> = String::from_utf8_lossy(&o.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect();
+        if conflicts.is_empty() {
+            return Err(Error::MergeError(branch.to_string()));
+        }
+        Err(Error::MergeConflict(
+            branch.to_string(),
+            conflicts.join(", "),
+        ))
+    }
+
+    fn merge_command(&self, branch: &str, strategy: Option<&str>) -> Command {
+        let mut cmd = self.command();
+        cmd.arg("merge").arg("--no-edit");
+        if let Some(strategy) = strategy {
+            cmd.arg("-s").arg(strategy);
+        }
+        cmd.arg(branch);
+        cmd
+    }
+
     // TODO(sahid): rename to something like
     // commit_based_on_changelog().
     pub fn debcommit(&self) -> Result<()> {
-        Command::new("debcommit")
-            .current_dir(&self.workdir)
-            .arg("-a")
-            .status()?;
+        Self::check_commit(&self.output(&mut self.debcommit_command())?)
+    }
+
+    fn debcommit_command(&self) -> Command {
+        let mut cmd = Command::new("debcommit");
+        isolate(&mut cmd).current_dir(&self.workdir).arg("-a");
+        cmd
+    }
+
+    /// Maps the output of a failed commit to a `CommitError` with
+    /// what was reported, e.g. nothing to commit.
+    fn check_commit(o: &Output) -> Result<()> {
+        if !o.status.success() {
+            let mut out = String::from_utf8_lossy(&o.stdout).trim().to_string();
+            let err = String::from_utf8_lossy(&o.stderr);
+            if !err.trim().is_empty() {
+                out.push('\n');
+                out.push_str(err.trim());
+            }
+            return Err(Error::CommitError(out.trim().to_string()));
+        }
         Ok(())
     }
 
+    /// Appends `text` as a new paragraph to the message of the last
+    /// commit.
+    pub fn amend_message(&self, text: &str) -> Result<()> {
+        let o = self.query(self.command().arg("log").arg("-1").arg("--format=%B"))?;
+        if !o.status.success() {
+            return Err(Error::CommitError("unable to read last commit".to_string()));
+        }
+        let message = format!("{}\n\n{}", String::from_utf8_lossy(&o.stdout).trim(), text);
+        let o = self.run(
+            self.command()
+                .arg("commit")
+                .arg("--amend")
+                .arg("-m")
+                .arg(message),
+        )?;
+        if !o.success() {
+            return Err(Error::CommitError(
+                "unable to amend last commit".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the output of git diff --stat for `range` (e.g.
+    /// 18.2.0..upstream).
+    pub fn diff_stat(&self, range: &str) -> Result<String> {
+        let o = self.query(self.command().arg("diff").arg("--stat").arg(range))?;
+        if !o.status.success() {
+            return Err(Error::DiffError(range.to_string()));
+        }
+        Ok(String::from_utf8_lossy(&o.stdout).trim_end().to_string())
+    }
+
+    /// Returns the `n` last commits as (short hash, subject).
+    pub fn log(&self, n: usize) -> Result<Vec<(String, String)>> {
+        let o = self.query(
+            self.command()
+                .arg("log")
+                .arg("--oneline")
+                .arg("-n")
+                .arg(n.to_string()),
+        )?;
+        if !o.status.success() {
+            return Err(Error::LogError("HEAD".to_string()));
+        }
+        Ok(String::from_utf8_lossy(&o.stdout)
+            .lines()
+            .map(|l| match l.split_once(' ') {
+                Some((hash, subject)) => (hash.to_string(), subject.to_string()),
+                None => (l.to_string(), String::new()),
+            })
+            .collect())
+    }
+
+    /// Returns the commits of `range` (e.g. 1.0.0..1.1.0) in the
+    /// oneline format.
+    pub fn log_oneline(&self, range: &str) -> Result<Vec<String>> {
+        let o = self.query(self.command().arg("log").arg("--oneline").arg(range))?;
+        if !o.status.success() {
+            return Err(Error::LogError(range.to_string()));
+        }
+        Ok(String::from_utf8_lossy(&o.stdout)
+            .lines()
+            .map(|l| l.to_string())
+            .collect())
+    }
+
     pub fn show(&self) -> Result<()> {
-        let o = Command::new("git")
-            .current_dir(&self.workdir)
-            .arg("show")
-            .status()?;
+        let o = self.run(self.command().arg("show"))?;
         if !o.success() {
             return Err(Error::ShowError());
         }
         Ok(())
     }
 
+    /// Applies the patch downloaded from `url`, no shell is involved
+    /// so `url` is never interpreted.
     pub fn apply_from_url(&self, url: &str) -> Result<()> {
-        let o = Command::new("/bin/sh")
-            .current_dir(&self.workdir)
-            .arg("-c")
-            .arg(format!("curl -L {} -sSf | git apply", url))
-            .status()?;
-        if !o.success() {
+        let o = self.output(Command::new("curl").arg("-L").arg("-sSf").arg(url))?;
+        if !o.status.success() {
+            return Err(Error::ApplyError());
+        }
+        let status = run_with_input(self.command().arg("apply"), &o.stdout, self.dry_run)?;
+        if !status.success() {
             return Err(Error::ApplyError());
         }
         Ok(())
     }
 
-    pub fn apply_from_file(&self, patch: PathBuf) -> Result<()> {
-        let o = Command::new("git")
-            .current_dir(&self.workdir)
-            .arg("apply")
-            .arg(patch)
-            .status()?;
-        if !o.success() {
-            return Err(Error::ApplyError());
+    /// Applies `patch` removing `strip` leading components of the
+    /// paths (i.e. -p<n>). When `three_way` is set and the patch does
+    /// not apply, a 3-way merge is attempted.
+    pub fn apply_from_file(
+        &self,
+        patch: PathBuf,
+        strip: Option<u8>,
+        three_way: bool,
+    ) -> Result<()> {
+        if self
+            .run(&mut self.apply_command(&patch, strip, false))?
+            .success()
+        {
+            return Ok(());
         }
-        Ok(())
+        if three_way
+            && self
+                .run(&mut self.apply_command(&patch, strip, true))?
+                .success()
+        {
+            return Ok(());
+        }
+        Err(Error::ApplyError())
+    }
+
+    fn apply_command(&self, patch: &Path, strip: Option<u8>, three_way: bool) -> Command {
+        let mut cmd = self.command();
+        cmd.arg("apply");
+        if let Some(strip) = strip {
+            cmd.arg(format!("-p{}", strip));
+        }
+        if three_way {
+            cmd.arg("--3way");
+        }
+        cmd.arg(patch);
+        cmd
     }
 
     // TODO(sahid): rename to pull
     pub fn update(&self) -> Result<()> {
-        let o = Command::new("git")
-            .current_dir(&self.workdir)
-            .arg("pull")
-            .status()?;
+        let o = self.run(self.command().arg("pull"))?;
         if !o.success() {
             return Err(Error::PullError());
         }
         Ok(())
     }
 
-    pub fn push(&self, url: &str) -> Result<()> {
-        let o = Command::new("git")
-            .current_dir(&self.workdir)
-            .arg("push")
-            .arg("-f")
-            .arg("--all")
-            .arg(url)
-            .status()?;
+    pub fn add_remote(&self, name: &str, url: &str) -> Result<()> {
+        if self.remotes()?.iter().any(|(n, _)| n == name) {
+            return Err(Error::RemoteExistsError(name.to_string()));
+        }
+        let o = self.run(self.command().arg("remote").arg("add").arg(name).arg(url))?;
+        if !o.success() {
+            return Err(Error::RemoteError(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Points the remote `name` to `url`.
+    pub fn set_remote_url(&self, name: &str, url: &str) -> Result<()> {
+        let o = self.run(
+            self.command()
+                .arg("remote")
+                .arg("set-url")
+                .arg(name)
+                .arg(url),
+        )?;
+        if !o.success() {
+            return Err(Error::RemoteError(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Returns the remotes configured as (name, url).
+    pub fn remotes(&self) -> Result<Vec<(String, String)>> {
+        let o = self.query(self.command().arg("remote").arg("-v"))?;
+        if !o.status.success() {
+            return Err(Error::RemoteError("list".to_string()));
+        }
+        // Each remote is listed twice, for fetch and push.
+        let mut remotes: Vec<(String, String)> = Vec::new();
+        for line in String::from_utf8_lossy(&o.stdout).lines() {
+            let mut fields = line.split_whitespace();
+            if let (Some(name), Some(url)) = (fields.next(), fields.next()) {
+                if !remotes.iter().any(|(n, _)| n == name) {
+                    remotes.push((name.to_string(), url.to_string()));
+                }
+            }
+        }
+        Ok(remotes)
+    }
+
+    /// Fetches `refspec` from `remote` without merging, everything
+    /// configured for the remote is fetched when not set.
+    pub fn fetch(&self, remote: &str, refspec: Option<&str>) -> Result<()> {
+        let mut cmd = self.command();
+        cmd.arg("fetch").arg(remote);
+        if let Some(refspec) = refspec {
+            cmd.arg(refspec);
+        }
+        if !self.run(&mut cmd)?.success() {
+            return Err(Error::FetchError(remote.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Pushes `branch` to `remote`, overwriting the remote branch
+    /// when `force` is set.
+    pub fn push_branch(&self, remote: &str, branch: &str, force: bool) -> Result<()> {
+        if !self
+            .run(&mut self.push_command(remote, branch, force))?
+            .success()
+        {
+            return Err(Error::PushError(format!("{} {}", remote, branch)));
+        }
+        Ok(())
+    }
+
+    fn push_command(&self, remote: &str, branch: &str, force: bool) -> Command {
+        let mut cmd = self.command();
+        cmd.arg("push");
+        if force {
+            cmd.arg("-f");
+        }
+        cmd.arg(remote).arg(branch);
+        cmd
+    }
+
+    /// Force pushes all the branches to `url`, every branch of the
+    /// remote is overwritten.
+    pub fn force_all(&self, url: &str) -> Result<()> {
+        let o = self.run(self.command().arg("push").arg("-f").arg("--all").arg(url))?;
         if !o.success() {
             return Err(Error::PushError(url.to_string()));
         }
         Ok(())
     }
 
+    /// Pushes all the refs and tags to `target`, a remote or an URL,
+    /// which becomes an exact copy of the repository. Mirroring to
+    /// origin is refused.
+    pub fn mirror(&self, target: &str) -> Result<()> {
+        let remotes = self.remotes()?;
+        let url = match remotes.iter().find(|(name, _)| name == target) {
+            Some((_, url)) => url.clone(),
+            None if is_git_url(target) => target.to_string(),
+            None => return Err(Error::UrlError(target.to_string())),
+        };
+        let origin = remotes.iter().find(|(name, _)| name == "origin");
+        if target == "origin" || origin.map(|(_, o)| o) == Some(&url) {
+            return Err(Error::MirrorOriginError(url));
+        }
+        if !self.run(&mut self.mirror_command(target))?.success() {
+            return Err(Error::PushError(url));
+        }
+        Ok(())
+    }
+
+    fn mirror_command(&self, target: &str) -> Command {
+        let mut cmd = self.command();
+        cmd.arg("push").arg("--mirror").arg(target);
+        cmd
+    }
+
+    /// Returns the hash `refname` points to, or `None` when the
+    /// reference does not exist.
+    pub fn ref_hash(&self, refname: &str) -> Result<Option<String>> {
+        let o = self.query(
+            self.command()
+                .arg("rev-parse")
+                .arg("--verify")
+                .arg("--quiet")
+                .arg(refname),
+        )?;
+        if !o.status.success() {
+            return Ok(None);
+        }
+        Ok(Some(String::from_utf8_lossy(&o.stdout).trim().to_string()))
+    }
+
+    /// Indicates whether the working tree has no changes, untracked
+    /// files included.
+    pub fn is_clean(&self) -> Result<bool> {
+        let o = self.query(self.command().arg("status").arg("--porcelain"))?;
+        if !o.status.success() {
+            return Err(Error::Fatal("unable to get status".to_string()));
+        }
+        Ok(o.stdout.iter().all(|c| c.is_ascii_whitespace()))
+    }
+
+    /// Indicates whether the local `branch` exists.
+    pub fn branch_exists(&self, branch: &str) -> Result<bool> {
+        Ok(self.ref_hash(&format!("refs/heads/{}", branch))?.is_some())
+    }
+
+    /// Indicates whether origin/`branch` exists.
+    pub fn remote_branch_exists(&self, branch: &str) -> Result<bool> {
+        Ok(self
+            .ref_hash(&format!("refs/remotes/origin/{}", branch))?
+            .is_some())
+    }
+
+    pub fn has_tag(&self, tag: &str) -> Result<bool> {
+        Ok(self.ref_hash(&format!("refs/tags/{}", tag))?.is_some())
+    }
+
+    /// Tags HEAD as `name`, the tag is annotated when a `message` is
+    /// provided.
+    pub fn tag(&self, name: &str, message: Option<&str>) -> Result<()> {
+        let mut cmd = self.command();
+        cmd.arg("tag");
+        if let Some(message) = message {
+            cmd.arg("-a").arg("-m").arg(message);
+        }
+        cmd.arg(name);
+        if !self.run(&mut cmd)?.success() {
+            return Err(Error::TagError(name.to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn tags(&self) -> Result<Vec<String>> {
+        let o = self.query(self.command().arg("tag").arg("-l"))?;
+        if !o.status.success() {
+            return Err(Error::TagError("list".to_string()));
+        }
+        Ok(String::from_utf8_lossy(&o.stdout)
+            .lines()
+            .map(|l| l.to_string())
+            .collect())
+    }
+
+    /// Returns the paths of the worktrees, the main one included.
+    pub fn worktrees(&self) -> Result<Vec<PathBuf>> {
+        let o = self.query(
+            self.command()
+                .arg("worktree")
+                .arg("list")
+                .arg("--porcelain"),
+        )?;
+        if !o.status.success() {
+            return Err(Error::WorktreeError("list".to_string()));
+        }
+        Ok(Self::parse_worktrees(&String::from_utf8_lossy(&o.stdout)))
+    }
+
+    /// Returns the paths listed by git worktree list --porcelain, the
+    /// main worktree first.
+    fn parse_worktrees(output: &str) -> Vec<PathBuf> {
+        output
+            .lines()
+            .filter_map(|l| l.strip_prefix("worktree "))
+            .map(PathBuf::from)
+            .collect()
+    }
+
+    /// Indicates whether `workdir` is a worktree linked to another
+    /// checkout rather than the main one.
+    pub fn is_linked_worktree(&self) -> Result<bool> {
+        let main = match self.worktrees()?.into_iter().next() {
+            Some(main) => main,
+            None => return Ok(false),
+        };
+        Ok(main.canonicalize()? != self.workdir.canonicalize()?)
+    }
+
+    /// Removes the administrative data of the worktrees whose
+    /// directories vanished.
+    pub fn prune_worktrees(&self) -> Result<()> {
+        let o = self.run(self.command().arg("worktree").arg("prune"))?;
+        if !o.success() {
+            return Err(Error::WorktreeError("prune".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Cleans up unnecessary files and optimizes the repository.
+    pub fn gc(&self, aggressive: bool) -> Result<()> {
+        if !self.run(&mut self.gc_command(aggressive))?.success() {
+            return Err(Error::GcError());
+        }
+        Ok(())
+    }
+
+    fn gc_command(&self, aggressive: bool) -> Command {
+        let mut cmd = self.command();
+        cmd.arg("gc");
+        if aggressive {
+            cmd.arg("--aggressive");
+        }
+        cmd
+    }
+
+    /// Checks the connectivity of the objects of the repository,
+    /// fails with the problems reported by git fsck.
+    pub fn fsck(&self) -> Result<()> {
+        let o = self.query(self.command().arg("fsck").arg("--connectivity-only"))?;
+        let problems = Self::parse_fsck(&format!(
+            "{}{}",
+            String::from_utf8_lossy(&o.stdout),
+            String::from_utf8_lossy(&o.stderr)
+        ));
+        if !o.status.success() || !problems.is_empty() {
+            let reason = if problems.is_empty() {
+                "git fsck failed".to_string()
+            } else {
+                problems.join("; ")
+            };
+            return Err(Error::FsckError(self.workdir.display().to_string(), reason));
+        }
+        Ok(())
+    }
+
+    /// Returns the problems found in the output of git fsck, dangling
+    /// objects are harmless and so ignored.
+    fn parse_fsck(output: &str) -> Vec<String> {
+        output
+            .lines()
+            .map(str::trim)
+            .filter(|l| {
+                l.starts_with("error")
+                    || l.starts_with("fatal")
+                    || l.starts_with("missing")
+                    || l.starts_with("broken link")
+                    || l.starts_with("bad")
+            })
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Same as `get_hash` but returns the full hash of HEAD.
+    pub fn get_full_hash(&self) -> Result<String> {
+        let o = self.query(self.command().arg("rev-parse").arg("HEAD"))?;
+        if !o.status.success() {
+            return Err(Error::HashError());
+        }
+        Ok(String::from_utf8_lossy(&o.stdout).trim().to_string())
+    }
+
     pub fn get_hash(&self) -> Result<String> {
-        let o = Command::new("git")
-            .current_dir(&self.workdir)
-            .arg("rev-parse")
-            .arg("--short")
-            .arg("HEAD")
-            .output()?;
+        let o = self.query(self.command().arg("rev-parse").arg("--short").arg("HEAD"))?;
         if !o.status.success() {
             return Err(Error::HashError());
         }
         Ok(String::from_utf8(o.stdout).unwrap().trim().to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Repository with an initial commit in a temporary directory,
+    /// removed on drop.
+    struct TempRepo {
+        rootdir: PathBuf,
+        git: Git,
+    }
+
+    impl TempRepo {
+        fn new(name: &str) -> TempRepo {
+            let rootdir =
+                std::env::temp_dir().join(format!("uosp-git-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&rootdir);
+            let mut git = Git::init(rootdir.clone(), "repo").unwrap();
+            git.options.global_args = ["-c", "user.name=uosp", "-c", "user.email=uosp@localhost"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect();
+            let repo = TempRepo { rootdir, git };
+            repo.commit("init");
+            repo
+        }
+
+        /// Commits all the changes of the working tree as `subject`.
+        fn commit(&self, subject: &str) {
+            let o = self
+                .git
+                .command()
+                .args(["commit", "-q", "-a", "--allow-empty", "-m", subject])
+                .status()
+                .unwrap();
+            assert!(o.success());
+        }
+
+        fn git(&self, args: &[&str]) {
+            assert!(self.git.command().args(args).status().unwrap().success());
+        }
+
+        /// Returns the trimmed output of a git command.
+        fn stdout(&self, args: &[&str]) -> String {
+            let o = self.git.command().args(args).output().unwrap();
+            String::from_utf8_lossy(&o.stdout).trim().to_string()
+        }
+    }
+
+    impl Drop for TempRepo {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.rootdir);
+        }
+    }
+
+    fn args(cmd: &Command) -> Vec<String> {
+        cmd.get_args()
+            .map(|a| a.to_string_lossy().to_string())
+            .collect()
+    }
+
+    fn has_isolation_env(cmd: &Command) -> bool {
+        let envs: Vec<_> = cmd.get_envs().collect();
+        isolation_env().iter().all(|(key, value)| {
+            envs.contains(&(std::ffi::OsStr::new(key), Some(std::ffi::OsStr::new(value))))
+        })
+    }
+
+    #[test]
+    fn isolate_commands() {
+        let repo = TempRepo::new("isolate");
+        assert!(!has_isolation_env(isolate(&mut Command::new("gbp"))));
+        set_isolated(true);
+        assert!(has_isolation_env(isolate(&mut Command::new("gbp"))));
+        assert!(has_isolation_env(isolate(&mut Command::new("debcommit"))));
+        assert!(has_isolation_env(&repo.git.command()));
+        set_isolated(false);
+        assert!(!has_isolation_env(&repo.git.command()));
+    }
+
+    #[test]
+    fn apply_from_url_does_not_substitute_commands() {
+        let rootdir = std::env::temp_dir().join(format!("uosp-git-{}", std::process::id()));
+        let git = Git::init(rootdir.clone(), "repo").unwrap();
+
+        // The patch is stored under a directory literally named
+        // $(whoami), it is only found when the URL is not expanded.
+        let patches = rootdir.join("$(whoami)");
+        std::fs::create_dir_all(&patches).unwrap();
+        std::fs::write(
+            patches.join("new.patch"),
+            "diff --git a/new b/new\n\
+             new file mode 100644\n\
+             --- /dev/null\n\
+             +++ b/new\n\
+             @@ -0,0 +1 @@\n\
+             +hello\n",
+        )
+        .unwrap();
+
+        let url = format!("file://{}/new.patch", patches.display());
+        let applied = git.apply_from_url(&url);
+        let created = rootdir.join("repo").join("new").exists();
+        std::fs::remove_dir_all(&rootdir).unwrap();
+        applied.unwrap();
+        assert!(created);
+    }
+
+    #[test]
+    fn push_branch_without_force() {
+        let git = Git::open(PathBuf::from("/nonexistent"));
+        let cmd = git.push_command("sahid-ferdjaoui", "master", false);
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, ["push", "sahid-ferdjaoui", "master"]);
+
+        let cmd = git.push_command("sahid-ferdjaoui", "master", true);
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, ["push", "-f", "sahid-ferdjaoui", "master"]);
+    }
+
+    #[test]
+    fn dry_run_runs_queries() {
+        let rootdir = std::env::temp_dir().join(format!("uosp-git-dry-{}", std::process::id()));
+        Git::init(rootdir.clone(), "repo").unwrap();
+        let status = Command::new("git")
+            .current_dir(rootdir.join("repo"))
+            .args(["-c", "user.name=uosp", "-c", "user.email=uosp@localhost"])
+            .args(["commit", "-q", "--allow-empty", "-m", "init"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let git = Git::new("repo", rootdir.clone(), GitCloneUrl::VCSGit, true).unwrap();
+        let branch = git.current_branch();
+        let clean = git.is_clean();
+        let tagged = git.tag("v1", None).and_then(|_| git.has_tag("v1"));
+        std::fs::remove_dir_all(&rootdir).unwrap();
+        assert_eq!(branch.unwrap(), "master");
+        assert!(clean.unwrap());
+        assert!(!tagged.unwrap());
+    }
+
+    #[test]
+    fn mirror_command() {
+        let git = Git::open(PathBuf::from("/nonexistent"));
+        assert_eq!(
+            args(&git.mirror_command("backup")),
+            ["push", "--mirror", "backup"]
+        );
+    }
+
+    #[test]
+    fn mirror_refuses_origin() {
+        let repo = TempRepo::new("mirror");
+        let backup = repo.rootdir.join("backup.git");
+        assert!(Command::new("git")
+            .arg("init")
+            .arg("-q")
+            .arg("--bare")
+            .arg(&backup)
+            .status()
+            .unwrap()
+            .success());
+        let origin = repo.rootdir.join("origin.git").display().to_string();
+        repo.git(&["remote", "add", "origin", &origin]);
+        repo.git(&["remote", "add", "backup", &backup.display().to_string()]);
+
+        assert!(matches!(
+            repo.git.mirror("origin"),
+            Err(Error::MirrorOriginError(_))
+        ));
+        assert!(matches!(
+            repo.git.mirror(&origin),
+            Err(Error::MirrorOriginError(_))
+        ));
+        assert!(matches!(
+            repo.git.mirror("nowhere"),
+            Err(Error::UrlError(_))
+        ));
+        repo.git.mirror("backup").unwrap();
+        assert!(Git::open(backup).branch_exists("master").unwrap());
+    }
+
+    #[test]
+    fn set_upstream() {
+        let repo = TempRepo::new("set-upstream");
+        let workdir = repo.rootdir.join("repo");
+        repo.git(&["remote", "add", "origin", workdir.to_str().unwrap()]);
+        repo.git(&["fetch", "-q", "origin"]);
+        let branch = repo.stdout(&["rev-parse", "--abbrev-ref", "HEAD"]);
+        repo.git
+            .set_upstream(&branch, &format!("origin/{}", branch))
+            .unwrap();
+        assert_eq!(
+            repo.stdout(&["rev-parse", "--abbrev-ref", "@{upstream}"]),
+            format!("origin/{}", branch)
+        );
+        match repo.git.set_upstream(&branch, "origin/nowhere") {
+            Err(Error::TrackError(b)) => assert_eq!(b, branch),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn is_git_repo() {
+        let repo = TempRepo::new("is-git-repo");
+        assert!(repo.git.is_git_repo());
+        assert_eq!(repo.stdout(&["symbolic-ref", "HEAD"]), "refs/heads/master");
+        let plain = repo.rootdir.join("plain");
+        std::fs::create_dir_all(&plain).unwrap();
+        assert!(!Git::open(plain).is_git_repo());
+        assert!(!Git::open(repo.rootdir.join("missing")).is_git_repo());
+    }
+
+    #[test]
+    fn rename_branch() {
+        let repo = TempRepo::new("rename-branch");
+        repo.git(&["branch", "stable/stein"]);
+        repo.git.rename_branch("stable/stein", "stein").unwrap();
+        assert!(!repo.git.branch_exists("stable/stein").unwrap());
+        assert!(repo.git.branch_exists("stein").unwrap());
+
+        // An existing branch is never overwritten.
+        let hash = repo.stdout(&["rev-parse", "stein"]);
+        repo.commit("second");
+        match repo.git.rename_branch("master", "stein") {
+            Err(Error::BranchExistsError(b)) => assert_eq!(b, "stein"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(repo.stdout(&["rev-parse", "stein"]), hash);
+        match repo.git.rename_branch("nowhere", "elsewhere") {
+            Err(Error::RenameError(b)) => assert_eq!(b, "nowhere"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn gc_command() {
+        let git = Git::open(PathBuf::from("/nonexistent"));
+        assert_eq!(args(&git.gc_command(false)), ["gc"]);
+        assert_eq!(args(&git.gc_command(true)), ["gc", "--aggressive"]);
+    }
+
+    #[test]
+    fn prune_worktrees() {
+        let repo = TempRepo::new("prune-worktrees");
+        let worktree = repo.rootdir.join("stein");
+        repo.git(&["worktree", "add", "-q", worktree.to_str().unwrap()]);
+        assert_eq!(repo.git.worktrees().unwrap().len(), 2);
+        std::fs::remove_dir_all(&worktree).unwrap();
+        repo.git.prune_worktrees().unwrap();
+        assert_eq!(repo.git.worktrees().unwrap(), [repo.rootdir.join("repo")]);
+        repo.git.gc(false).unwrap();
+    }
+
+    /// Returns the output of `script` run by sh.
+    fn sh(script: &str) -> Output {
+        Command::new("sh").arg("-c").arg(script).output().unwrap()
+    }
+
+    #[test]
+    fn debcommit() {
+        let git = Git::open(PathBuf::from("/tmp/nova"));
+        let cmd = git.debcommit_command();
+        assert_eq!(cmd.get_program(), "debcommit");
+        assert_eq!(cmd.get_current_dir(), Some(Path::new("/tmp/nova")));
+        assert_eq!(args(&cmd), ["-a"]);
+
+        Git::check_commit(&sh("echo '[master 86823b5] New upstream release.'")).unwrap();
+        match Git::check_commit(&sh(
+            "echo 'On branch master'; echo 'nothing to commit, working tree clean'; \
+             echo 'debcommit: commit failed' >&2; exit 1",
+        )) {
+            Err(Error::CommitError(e)) => assert_eq!(
+                e,
+                "On branch master\nnothing to commit, working tree clean\n\
+                 debcommit: commit failed"
+            ),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn branch_exists() {
+        let repo = TempRepo::new("branch-exists");
+        assert!(repo.git.branch_exists("master").unwrap());
+        assert!(!repo.git.branch_exists("upstream").unwrap());
+        repo.git(&["branch", "upstream"]);
+        assert!(repo.git.branch_exists("upstream").unwrap());
+        // Tags are not branches.
+        repo.git(&["tag", "pristine-tar"]);
+        assert!(!repo.git.branch_exists("pristine-tar").unwrap());
+    }
+
+    #[test]
+    fn create_branch() {
+        let repo = TempRepo::new("create-branch");
+        repo.git.create_branch("stable/stein", None).unwrap();
+        assert!(repo.git.branch_exists("stable/stein").unwrap());
+        assert_eq!(repo.git.current_branch().unwrap(), "stable/stein");
+
+        let init = repo.stdout(&["rev-parse", "HEAD"]);
+        repo.commit("second");
+        repo.git.create_branch("stable/rocky", Some(&init)).unwrap();
+        assert_eq!(repo.stdout(&["rev-parse", "HEAD"]), init);
+
+        match repo.git.create_branch("stable/stein", None) {
+            Err(Error::BranchExistsError(b)) => assert_eq!(b, "stable/stein"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match repo.git.create_branch("stable/queens", Some("nowhere")) {
+            Err(Error::CheckoutError(b)) => assert_eq!(b, "stable/queens"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn checkout_tracks_remote_branch() {
+        let repo = TempRepo::new("checkout-track");
+        let workdir = repo.rootdir.join("repo");
+        repo.git(&["branch", "upstream"]);
+        repo.git(&["remote", "add", "origin", workdir.to_str().unwrap()]);
+        repo.git(&["fetch", "-q", "origin"]);
+        repo.git(&["branch", "-D", "upstream"]);
+        repo.git(&["branch", "stable/stein"]);
+
+        // Local branch, nothing to track.
+        repo.git.checkout("stable/stein").unwrap();
+        assert_eq!(repo.git.current_branch().unwrap(), "stable/stein");
+        assert_eq!(repo.stdout(&["config", "branch.stable/stein.remote"]), "");
+
+        // Only origin/upstream, a local branch tracking it is created.
+        assert!(!repo.git.branch_exists("upstream").unwrap());
+        repo.git.checkout("upstream").unwrap();
+        assert_eq!(repo.git.current_branch().unwrap(), "upstream");
+        assert_eq!(
+            repo.stdout(&["rev-parse", "--abbrev-ref", "@{upstream}"]),
+            "origin/upstream"
+        );
+
+        match repo.git.checkout("pristine-tar") {
+            Err(Error::CheckoutError(b)) => assert_eq!(b, "pristine-tar"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(repo.git.current_branch().unwrap(), "upstream");
+    }
+
+    #[test]
+    fn fetch() {
+        let repo = TempRepo::new("fetch");
+        let workdir = repo.rootdir.join("repo");
+        repo.git(&["branch", "upstream"]);
+        repo.git(&["branch", "pristine-tar"]);
+        repo.git(&["remote", "add", "upstream", workdir.to_str().unwrap()]);
+
+        repo.git
+            .fetch(
+                "upstream",
+                Some("refs/heads/upstream:refs/remotes/upstream/upstream"),
+            )
+            .unwrap();
+        assert!(repo
+            .git
+            .ref_hash("refs/remotes/upstream/upstream")
+            .unwrap()
+            .is_some());
+        assert!(repo
+            .git
+            .ref_hash("refs/remotes/upstream/pristine-tar")
+            .unwrap()
+            .is_none());
+
+        repo.git.fetch("upstream", None).unwrap();
+        assert!(repo
+            .git
+            .ref_hash("refs/remotes/upstream/pristine-tar")
+            .unwrap()
+            .is_some());
+
+        match repo.git.fetch("nowhere", None) {
+            Err(Error::FetchError(r)) => assert_eq!(r, "nowhere"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn is_clean() {
+        let repo = TempRepo::new("is-clean");
+        let workdir = repo.rootdir.join("repo");
+        assert!(repo.git.is_clean().unwrap());
+        std::fs::write(workdir.join("setup.cfg"), "[metadata]\n").unwrap();
+        assert!(!repo.git.is_clean().unwrap());
+        repo.git(&["add", "setup.cfg"]);
+        assert!(!repo.git.is_clean().unwrap());
+        repo.commit("add setup.cfg");
+        assert!(repo.git.is_clean().unwrap());
+        std::fs::write(workdir.join("setup.cfg"), "[metadata]\nname = nova\n").unwrap();
+        assert!(!repo.git.is_clean().unwrap());
+    }
+
+    #[test]
+    fn reset_hard() {
+        let repo = TempRepo::new("reset-hard");
+        let workdir = repo.rootdir.join("repo");
+        let init = repo.stdout(&["rev-parse", "HEAD"]);
+        std::fs::write(workdir.join("setup.cfg"), "[metadata]\n").unwrap();
+        repo.git(&["add", "setup.cfg"]);
+        repo.commit("add setup.cfg");
+        std::fs::write(workdir.join("setup.cfg"), "[metadata]\nname = nova\n").unwrap();
+
+        repo.git.reset_hard("HEAD").unwrap();
+        assert!(repo.git.is_clean().unwrap());
+        assert_eq!(
+            std::fs::read_to_string(workdir.join("setup.cfg")).unwrap(),
+            "[metadata]\n"
+        );
+        repo.git.reset_hard(&init).unwrap();
+        assert_eq!(repo.stdout(&["rev-parse", "HEAD"]), init);
+        assert!(!workdir.join("setup.cfg").exists());
+        match repo.git.reset_hard("nowhere") {
+            Err(Error::ResetError(r)) => assert_eq!(r, "nowhere"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn diff_stat() {
+        let repo = TempRepo::new("diff-stat");
+        let workdir = repo.rootdir.join("repo");
+        std::fs::write(workdir.join("setup.cfg"), "[metadata]\nname = nova\n").unwrap();
+        repo.git(&["add", "setup.cfg"]);
+        repo.commit("add setup.cfg");
+        let stat = repo
+            .git
+            .diff_stat(&format!("{}..HEAD", EMPTY_TREE))
+            .unwrap();
+        assert_eq!(stat, " setup.cfg | 2 ++\n 1 file changed, 2 insertions(+)");
+        assert_eq!(repo.git.diff_stat("HEAD..HEAD").unwrap(), "");
+        match repo.git.diff_stat("nowhere..HEAD") {
+            Err(Error::DiffError(r)) => assert_eq!(r, "nowhere..HEAD"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn log() {
+        let repo = TempRepo::new("log");
+        repo.commit("Imported upstream version 19.0.0");
+        repo.commit("New upstream release");
+        let log = repo.git.log(2).unwrap();
+        let hashes = repo.stdout(&["log", "--format=%h", "-n", "2"]);
+        let hashes: Vec<_> = hashes.lines().collect();
+        assert_eq!(
+            log,
+            [
+                (hashes[0].to_string(), "New upstream release".to_string()),
+                (
+                    hashes[1].to_string(),
+                    "Imported upstream version 19.0.0".to_string()
+                ),
+            ]
+        );
+        assert_eq!(repo.git.log(10).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn get_hash() {
+        let repo = TempRepo::new("get-hash");
+        let full = repo.git.get_full_hash().unwrap();
+        assert_eq!(full.len(), 40);
+        assert!(full.chars().all(|c| c.is_ascii_hexdigit()));
+        let short = repo.git.get_hash().unwrap();
+        assert!(short.len() >= 7 && short.len() < 40);
+        assert!(full.starts_with(&short));
+
+        assert!(matches!(
+            Git::open(repo.rootdir.clone()).get_full_hash(),
+            Err(Error::HashError())
+        ));
+    }
+
+    #[test]
+    fn parse_worktrees() {
+        let output = "worktree /srv/packages/nova\n\
+                      HEAD 86823b5c4c8b3f2a1e0b9d7c6a5f4e3d2c1b0a99\n\
+                      branch refs/heads/master\n\
+                      \n\
+                      worktree /srv/packages/nova-stein\n\
+                      HEAD 1b0a9986823b5c4c8b3f2a1e0b9d7c6a5f4e3d2c\n\
+                      detached\n\
+                      \n\
+                      worktree /srv/packages/nova-rocky\n\
+                      HEAD 5f4e3d2c1b0a9986823b5c4c8b3f2a1e0b9d7c6a\n\
+                      branch refs/heads/stable/rocky\n\
+                      prunable gitdir file points to non-existent location\n";
+        assert_eq!(
+            Git::parse_worktrees(output),
+            [
+                PathBuf::from("/srv/packages/nova"),
+                PathBuf::from("/srv/packages/nova-stein"),
+                PathBuf::from("/srv/packages/nova-rocky"),
+            ]
+        );
+        assert!(Git::parse_worktrees("").is_empty());
+    }
+
+    #[test]
+    fn is_linked_worktree() {
+        let repo = TempRepo::new("linked-worktree");
+        let worktree = repo.rootdir.join("stein");
+        repo.git(&["worktree", "add", "-q", worktree.to_str().unwrap()]);
+        assert!(!repo.git.is_linked_worktree().unwrap());
+        assert!(Git::open(worktree).is_linked_worktree().unwrap());
+    }
+
+    #[test]
+    fn apply_command() {
+        let git = Git::open(PathBuf::from("/nonexistent"));
+        let patch = Path::new("/tmp/fix.patch");
+        assert_eq!(
+            args(&git.apply_command(patch, None, false)),
+            ["apply", "/tmp/fix.patch"]
+        );
+        assert_eq!(
+            args(&git.apply_command(patch, Some(1), false)),
+            ["apply", "-p1", "/tmp/fix.patch"]
+        );
+        assert_eq!(
+            args(&git.apply_command(patch, Some(0), true)),
+            ["apply", "-p0", "--3way", "/tmp/fix.patch"]
+        );
+    }
+
+    #[test]
+    fn apply_from_file_three_way() {
+        let repo = TempRepo::new("apply-3way");
+        let workdir = repo.rootdir.join("repo");
+        let lines: Vec<String> = (1..=10).map(|i| format!("line {}\n", i)).collect();
+        std::fs::write(workdir.join("file"), lines.concat()).unwrap();
+        repo.git(&["add", "file"]);
+        repo.commit("add file");
+        let mut patched = lines.clone();
+        patched[7] = "patched 8\n".to_string();
+        std::fs::write(workdir.join("file"), patched.concat()).unwrap();
+        let patch = repo.rootdir.join("fix.patch");
+        std::fs::write(&patch, repo.stdout(&["diff"]) + "\n").unwrap();
+        repo.git(&["checkout", "-q", "file"]);
+
+        // The context drifted, only a 3-way merge applies it.
+        let mut drifted = lines.clone();
+        drifted[5] = "changed 6\n".to_string();
+        std::fs::write(workdir.join("file"), drifted.concat()).unwrap();
+        repo.commit("change line 6");
+        assert!(matches!(
+            repo.git.apply_from_file(patch.clone(), Some(1), false),
+            Err(Error::ApplyError())
+        ));
+        repo.git.apply_from_file(patch, Some(1), true).unwrap();
+        let content = std::fs::read_to_string(workdir.join("file")).unwrap();
+        assert!(content.contains("changed 6\n") && content.contains("patched 8\n"));
+    }
+
+    #[test]
+    fn global_args_before_subcommand() {
+        let mut git = Git::open(PathBuf::from("/nonexistent"));
+        git.options = GitOptions {
+            git_bin: "/usr/local/bin/git".to_string(),
+            global_args: vec!["-c".to_string(), "user.email=uosp@localhost".to_string()],
+        };
+        let cmd = git.push_command("origin", "master", false);
+        assert_eq!(cmd.get_program(), "/usr/local/bin/git");
+        assert_eq!(
+            args(&cmd),
+            [
+                "-c",
+                "user.email=uosp@localhost",
+                "push",
+                "origin",
+                "master"
+            ]
+        );
+    }
+
+    #[test]
+    fn clone_url_from_spec() {
+        let gitlab = GitCloneUrl::from_spec("gitlab:group/proj").unwrap();
+        assert_eq!(gitlab, GitCloneUrl::GitLab("group/proj".to_string()));
+        assert_eq!(gitlab.to_string(), "https://gitlab.com/group/proj.git");
+
+        let host = GitCloneUrl::from_spec("git.example.org:/group/proj.git").unwrap();
+        assert_eq!(
+            host,
+            GitCloneUrl::Host {
+                host: "git.example.org".to_string(),
+                path: "group/proj".to_string(),
+            }
+        );
+        assert_eq!(host.to_string(), "https://git.example.org/group/proj.git");
+
+        assert_eq!(
+            GitCloneUrl::from_spec("https://opendev.org/openstack/nova"),
+            Some(GitCloneUrl::Plain(
+                "https://opendev.org/openstack/nova".to_string()
+            ))
+        );
+        assert_eq!(GitCloneUrl::from_spec("vcsgit"), Some(GitCloneUrl::VCSGit));
+        assert_eq!(GitCloneUrl::from_spec("gitlab:"), None);
+        assert_eq!(GitCloneUrl::from_spec("forge:group/proj"), None);
+        assert_eq!(GitCloneUrl::from_spec("nova"), None);
+    }
+
+    #[test]
+    fn parse_fsck() {
+        let output = "Checking object directories: 100% (256/256), done.\n\
+                      dangling blob 86823b5c4c8b3f2a1e0b9d7c6a5f4e3d2c1b0a99\n\
+                      broken link from    tree 1b0a9986823b5c4c8b3f2a1e0b9d7c6a5f4e3d2c\n\
+                      \x20             to    blob 5f4e3d2c1b0a9986823b5c4c8b3f2a1e0b9d7c6a\n\
+                      missing blob 5f4e3d2c1b0a9986823b5c4c8b3f2a1e0b9d7c6a\n\
+                      error: HEAD: invalid sha1 pointer\n";
+        assert_eq!(
+            Git::parse_fsck(output),
+            [
+                "broken link from    tree 1b0a9986823b5c4c8b3f2a1e0b9d7c6a5f4e3d2c",
+                "missing blob 5f4e3d2c1b0a9986823b5c4c8b3f2a1e0b9d7c6a",
+                "error: HEAD: invalid sha1 pointer",
+            ]
+        );
+        assert!(Git::parse_fsck("dangling commit 86823b5c\n").is_empty());
+    }
+
+    #[test]
+    fn fsck() {
+        let repo = TempRepo::new("fsck");
+        let workdir = repo.rootdir.join("repo");
+        std::fs::write(workdir.join("setup.cfg"), "[metadata]\n").unwrap();
+        repo.git(&["add", "setup.cfg"]);
+        repo.commit("add setup.cfg");
+        repo.git.fsck().unwrap();
+
+        let blob = repo.stdout(&["rev-parse", "HEAD:setup.cfg"]);
+        std::fs::remove_file(workdir.join(format!(".git/objects/{}/{}", &blob[..2], &blob[2..])))
+            .unwrap();
+        match repo.git.fsck() {
+            Err(Error::FsckError(path, reason)) => {
+                assert_eq!(path, workdir.display().to_string());
+                assert!(
+                    reason.contains(&format!("missing blob {}", blob)),
+                    "{}",
+                    reason
+                );
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn ssh_clone_urls() {
+        let salsa = GitCloneUrl::Salsa("openstack-team/services/nova".to_string()).ssh();
+        assert_eq!(
+            salsa.to_string(),
+            "git@salsa.debian.org:openstack-team/services/nova.git"
+        );
+        let github = GitCloneUrl::GitHub("sahid/uosp".to_string()).ssh();
+        assert_eq!(github.to_string(), "git@github.com:sahid/uosp.git");
+        // Already in the SSH form.
+        assert_eq!(github.clone().ssh(), github);
+        assert_eq!(
+            GitCloneUrl::Plain("https://opendev.org/openstack/nova".to_string())
+                .ssh()
+                .to_string(),
+            "https://opendev.org/openstack/nova"
+        );
+    }
+
+    #[test]
+    fn merge_command() {
+        let git = Git::open(PathBuf::from("/nonexistent"));
+        assert_eq!(
+            args(&git.merge_command("upstream", None)),
+            ["merge", "--no-edit", "upstream"]
+        );
+        assert_eq!(
+            args(&git.merge_command("upstream", Some("ours"))),
+            ["merge", "--no-edit", "-s", "ours", "upstream"]
+        );
+    }
+
+    #[test]
+    fn merge_conflict() {
+        let repo = TempRepo::new("merge-conflict");
+        let workdir = repo.rootdir.join("repo");
+        repo.git(&["checkout", "-q", "-b", "upstream"]);
+        std::fs::write(workdir.join("setup.cfg"), "[metadata]\nversion = 19.0.1\n").unwrap();
+        repo.git(&["add", "setup.cfg"]);
+        repo.commit("upstream");
+        repo.git(&["checkout", "-q", "master"]);
+        std::fs::write(workdir.join("setup.cfg"), "[metadata]\nversion = 19.0.0\n").unwrap();
+        repo.git(&["add", "setup.cfg"]);
+        repo.commit("packaging");
+
+        match repo.git.merge("upstream", None) {
+            Err(Error::MergeConflict(b, files)) => {
+                assert_eq!(b, "upstream");
+                assert_eq!(files, "setup.cfg");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        repo.git(&["merge", "--abort"]);
+        repo.git.merge("upstream", Some("ours")).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(workdir.join("setup.cfg")).unwrap(),
+            "[metadata]\nversion = 19.0.0\n"
+        );
+        assert!(matches!(
+            repo.git.merge("nowhere", None),
+            Err(Error::MergeError(_))
+        ));
+    }
+}
@@ -0,0 +1,305 @@
+// Copyright 2019 Canonical Ltd. All rights reserved.  Use
+// of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Runs the commands wrapped by uosp (git, gbp, debchange...).
+//!
+//! The behavior set from the command line (dry-run, timeout,
+//! non-interactive mode, prefixed output) is shared by all the
+//! commands run, whichever crate runs them.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::trace;
+
+/// Returns a printable form of `cmd` (e.g. cd /path && git show).
+pub fn describe(cmd: &Command) -> String {
+    let mut line = String::new();
+    if let Some(dir) = cmd.get_current_dir() {
+        line.push_str(&format!("cd {} && ", dir.display()));
+    }
+    line.push_str(&cmd.get_program().to_string_lossy());
+    for arg in cmd.get_args() {
+        line.push(' ');
+        line.push_str(&arg.to_string_lossy());
+    }
+    line
+}
+
+static NON_INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// When set the commands run never wait for an answer of the user:
+/// their standard input is closed and the tools are asked to not
+/// prompt (credentials for git, debconf...).
+pub fn set_non_interactive(non_interactive: bool) {
+    NON_INTERACTIVE.store(non_interactive, Ordering::Relaxed);
+}
+
+pub fn non_interactive() -> bool {
+    NON_INTERACTIVE.load(Ordering::Relaxed)
+}
+
+static PREFIX_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/// When set the output of the commands run on a repository is
+/// prefixed line by line with the name of the repository.
+pub fn set_prefix_output(prefix_output: bool) {
+    PREFIX_OUTPUT.store(prefix_output, Ordering::Relaxed);
+}
+
+pub fn prefix_output() -> bool {
+    PREFIX_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// Returns the environment used to run commands non-interactively.
+pub fn non_interactive_env() -> Vec<(&'static str, &'static str)> {
+    vec![
+        ("GIT_TERMINAL_PROMPT", "0"),
+        ("DEBIAN_FRONTEND", "noninteractive"),
+    ]
+}
+
+/// Configures `cmd` according to the non-interactive mode.
+pub fn prepare(cmd: &mut Command) {
+    if non_interactive() {
+        cmd.stdin(Stdio::null()).envs(non_interactive_env());
+    }
+}
+
+static TIMEOUT: AtomicU64 = AtomicU64::new(0);
+
+/// Sets the time the commands run are allowed to take before being
+/// killed, `None` lets them run indefinitely.
+pub fn set_timeout(timeout: Option<Duration>) {
+    TIMEOUT.store(timeout.map_or(0, |t| t.as_secs()), Ordering::Relaxed);
+}
+
+pub fn timeout() -> Option<Duration> {
+    match TIMEOUT.load(Ordering::Relaxed) {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    }
+}
+
+/// Waits for `child` up to `timeout`, the child is killed and an
+/// error of kind `TimedOut` is returned when it overruns.
+pub fn wait_timeout(child: &mut Child, timeout: Duration) -> io::Result<ExitStatus> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if start.elapsed() >= timeout {
+            child.kill()?;
+            child.wait()?;
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("killed after {}s", timeout.as_secs()),
+            ));
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Indicates whether `cmd` is a build, builds legitimately take hours
+/// so they are never killed by the timeout.
+fn is_build(cmd: &Command) -> bool {
+    match cmd.get_program().to_str() {
+        Some("sbuild") => true,
+        Some("gbp") => cmd.get_args().next() == Some("buildpackage".as_ref()),
+        _ => false,
+    }
+}
+
+/// Waits for `child` with the configured timeout, the error reports
+/// `cmd` when it overruns. Builds are not limited.
+pub fn wait(cmd: &Command, child: &mut Child) -> io::Result<ExitStatus> {
+    match timeout() {
+        Some(timeout) if !is_build(cmd) => {
+            wait_timeout(child, timeout).map_err(|e| match e.kind() {
+                io::ErrorKind::TimedOut => {
+                    io::Error::new(e.kind(), format!("{}, {}", describe(cmd), e))
+                }
+                _ => e,
+            })
+        }
+        _ => child.wait(),
+    }
+}
+
+/// Runs `cmd` and returns its exit status. In dry-run mode the
+/// command is only printed and considered as succeeded.
+pub fn run(cmd: &mut Command, dry_run: bool) -> io::Result<ExitStatus> {
+    if dry_run {
+        println!("[dry-run] {}", describe(cmd));
+        return Ok(ExitStatus::from_raw(0));
+    }
+    trace!("running {}", describe(cmd));
+    prepare(cmd);
+    let mut child = cmd.spawn()?;
+    wait(cmd, &mut child)
+}
+
+/// Same as `run` but captures the output of `cmd`, which is empty in
+/// dry-run mode.
+pub fn run_output(cmd: &mut Command, dry_run: bool) -> io::Result<Output> {
+    if dry_run {
+        println!("[dry-run] {}", describe(cmd));
+        return Ok(Output {
+            status: ExitStatus::from_raw(0),
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        });
+    }
+    trace!("running {}", describe(cmd));
+    prepare(cmd);
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    // Both pipes are drained while waiting so the child never blocks
+    // on a full pipe.
+    let mut stdout = child.stdout.take().unwrap();
+    let mut stderr = child.stderr.take().unwrap();
+    let stdout = thread::spawn(move || {
+        let mut buf = Vec::new();
+        stdout.read_to_end(&mut buf).map(|_| buf)
+    });
+    let stderr = thread::spawn(move || {
+        let mut buf = Vec::new();
+        stderr.read_to_end(&mut buf).map(|_| buf)
+    });
+    let status = wait(cmd, &mut child)?;
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap()?,
+        stderr: stderr.join().unwrap()?,
+    })
+}
+
+/// Same as `run` but writes `input` to the standard input of `cmd`.
+pub fn run_with_input(cmd: &mut Command, input: &[u8], dry_run: bool) -> io::Result<ExitStatus> {
+    if dry_run {
+        println!("[dry-run] {}", describe(cmd));
+        return Ok(ExitStatus::from_raw(0));
+    }
+    trace!("running {}", describe(cmd));
+    prepare(cmd);
+    let mut child = cmd.stdin(Stdio::piped()).spawn()?;
+    // The pipe is closed when stdin is dropped.
+    child.stdin.take().unwrap().write_all(input)?;
+    wait(cmd, &mut child)
+}
+
+/// Runs `first` with its standard output piped into `second`, as
+/// `first | second` in a shell. The exit status of both are returned
+/// so a failure of `first` is not hidden by `second`.
+pub fn run_piped(
+    first: &mut Command,
+    second: &mut Command,
+    dry_run: bool,
+) -> io::Result<(ExitStatus, ExitStatus)> {
+    if dry_run {
+        println!("[dry-run] {} | {}", describe(first), describe(second));
+        return Ok((ExitStatus::from_raw(0), ExitStatus::from_raw(0)));
+    }
+    trace!("running {} | {}", describe(first), describe(second));
+    prepare(first);
+    prepare(second);
+    let mut producer = first.stdout(Stdio::piped()).spawn()?;
+    let pipe = producer.stdout.take().unwrap();
+    let mut consumer = match second.stdin(pipe).spawn() {
+        Ok(consumer) => consumer,
+        Err(e) => {
+            let _ = producer.kill();
+            let _ = producer.wait();
+            return Err(e);
+        }
+    };
+    let consumed = wait(second, &mut consumer);
+    let produced = wait(first, &mut producer);
+    Ok((produced?, consumed?))
+}
+
+/// Forwards `input` line by line to `output`, each line prefixed with
+/// `[prefix]`.
+fn forward<R, W>(input: R, mut output: W, prefix: String) -> JoinHandle<()>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    thread::spawn(move || {
+        for line in BufReader::new(input).lines().map_while(io::Result::ok) {
+            // Nothing better to do than dropping the line when the
+            // output is closed.
+            let _ = writeln!(output, "[{}] {}", prefix, line);
+        }
+    })
+}
+
+/// Runs `cmd` with stdout and stderr piped, their lines are forwarded
+/// to the ones of the process prefixed with `[prefix]`.
+pub fn run_with_prefix(cmd: &mut Command, prefix: &str) -> io::Result<ExitStatus> {
+    trace!("running {}", describe(cmd));
+    prepare(cmd);
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let stdout = forward(
+        child.stdout.take().unwrap(),
+        io::stdout(),
+        prefix.to_string(),
+    );
+    let stderr = forward(
+        child.stderr.take().unwrap(),
+        io::stderr(),
+        prefix.to_string(),
+    );
+    let status = wait(cmd, &mut child)?;
+    stdout.join().unwrap();
+    stderr.join().unwrap();
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_not_timed() {
+        assert!(is_build(Command::new("sbuild").arg("-d")));
+        assert!(is_build(Command::new("gbp").args(["buildpackage", "-S"])));
+        assert!(!is_build(Command::new("gbp").arg("import-orig")));
+        assert!(!is_build(Command::new("git").arg("buildpackage")));
+    }
+    #[test]
+    fn wait_kills_after_timeout() {
+        let mut child = Command::new("sleep").arg("5").spawn().unwrap();
+        let e = wait_timeout(&mut child, Duration::from_millis(100)).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+    }
+    #[test]
+    fn dry_run_does_not_spawn() {
+        let status = run(&mut Command::new("/nonexistent/program"), true).unwrap();
+        assert!(status.success());
+        let o = run_output(&mut Command::new("/nonexistent/program"), true).unwrap();
+        assert!(o.status.success() && o.stdout.is_empty());
+    }
+
+    #[test]
+    fn non_interactive_commands() {
+        let script = "if read line; then echo read; else echo eof; fi; \
+                      echo $DEBIAN_FRONTEND $GIT_TERMINAL_PROMPT";
+        set_non_interactive(true);
+        let o = run_output(Command::new("sh").arg("-c").arg(script), false);
+        set_non_interactive(false);
+        assert_eq!(
+            String::from_utf8_lossy(&o.unwrap().stdout),
+            "eof\nnoninteractive 0\n"
+        );
+
+        let mut cmd = Command::new("sh");
+        prepare(&mut cmd);
+        assert_eq!(cmd.get_envs().count(), 0);
+    }
+}
@@ -0,0 +1,140 @@
+// Copyright 2019 Canonical Ltd. All rights reserved.  Use
+// of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! User configuration providing defaults to the commands.
+//!
+//! The configuration is read from `$XDG_CONFIG_HOME/uosp/config.toml`,
+//! the values set on the command line take precedence over it, which
+//! takes precedence over the built-in defaults.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::{Error, Result};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub default_ppa: Option<String>,
+    pub default_serie: Option<String>,
+    pub default_release: Option<String>,
+    pub maintainer_name: Option<String>,
+    pub maintainer_email: Option<String>,
+    /// Clone URL of the packages where `{name}` is replaced by the
+    /// package name (e.g. https://salsa.debian.org/openstack-team/services/{name}.git).
+    pub git_url_template: Option<String>,
+}
+
+impl Config {
+    /// Returns the path of the configuration file of the user.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("uosp").join("config.toml"))
+    }
+
+    /// Loads the configuration from `path`, or from the default path
+    /// when not set. A missing default configuration is empty.
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let path = match (path, Self::default_path()) {
+            (Some(path), _) => path.to_path_buf(),
+            (None, Some(path)) if path.exists() => path,
+            (None, _) => return Ok(Config::default()),
+        };
+        let content = fs::read_to_string(&path)
+            .map_err(|e| Error::ConfigError(path.display().to_string(), e.to_string()))?;
+        Self::parse(&content)
+            .map_err(|e| Error::ConfigError(path.display().to_string(), e.to_string()))
+    }
+
+    pub fn parse(content: &str) -> std::result::Result<Config, toml::de::Error> {
+        toml::from_str(content)
+    }
+
+    /// Returns `flag` or the value configured, then `default`.
+    pub fn resolve(flag: Option<&str>, configured: &Option<String>, default: &str) -> String {
+        flag.map(str::to_string)
+            .or_else(|| configured.clone())
+            .unwrap_or_else(|| default.to_string())
+    }
+
+    pub fn release(&self, flag: Option<&str>) -> String {
+        Self::resolve(flag, &self.default_release, "master")
+    }
+
+    pub fn ppa(&self, flag: Option<&str>) -> Result<String> {
+        flag.map(str::to_string)
+            .or_else(|| self.default_ppa.clone())
+            .ok_or_else(|| Error::OptionError("missing ppa, set default_ppa".to_string()))
+    }
+
+    pub fn serie(&self, flag: Option<&str>) -> Result<String> {
+        flag.map(str::to_string)
+            .or_else(|| self.default_serie.clone())
+            .ok_or_else(|| Error::OptionError("missing serie, set default_serie".to_string()))
+    }
+
+    /// Returns the maintainer signing the changelog entries, the name
+    /// and the email have to be configured together.
+    pub fn maintainer(&self) -> Option<(String, String)> {
+        match (&self.maintainer_name, &self.maintainer_email) {
+            (Some(name), Some(email)) => Some((name.to_string(), email.to_string())),
+            _ => None,
+        }
+    }
+
+    /// Returns the clone URL of `name` according to the template.
+    pub fn git_url(&self, name: &str) -> Option<String> {
+        self.git_url_template
+            .as_ref()
+            .map(|template| template.replace("{name}", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_precedence() {
+        let configured = Some("stein".to_string());
+        assert_eq!(
+            Config::resolve(Some("train"), &configured, "master"),
+            "train"
+        );
+        assert_eq!(Config::resolve(None, &configured, "master"), "stein");
+        assert_eq!(Config::resolve(None, &None, "master"), "master");
+    }
+
+    #[test]
+    fn parse_and_resolve() {
+        let config = Config::parse(
+            "default_serie = \"eoan\"\n\
+             git_url_template = \"https://salsa.debian.org/openstack-team/services/{name}.git\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.serie(None).unwrap(), "eoan");
+        assert_eq!(config.serie(Some("focal")).unwrap(), "focal");
+        assert!(config.ppa(None).is_err());
+        assert_eq!(config.release(None), "master");
+        assert_eq!(
+            config.git_url("nova").unwrap(),
+            "https://salsa.debian.org/openstack-team/services/nova.git"
+        );
+    }
+
+    #[test]
+    fn maintainer_requires_name_and_email() {
+        let mut config = Config::parse("maintainer_name = \"Sahid Orentino Ferdjaoui\"\n").unwrap();
+        assert_eq!(config.maintainer(), None);
+        config.maintainer_email = Some("sahid.ferdjaoui@canonical.com".to_string());
+        assert_eq!(
+            config.maintainer(),
+            Some((
+                "Sahid Orentino Ferdjaoui".to_string(),
+                "sahid.ferdjaoui@canonical.com".to_string()
+            ))
+        );
+    }
+}
@@ -5,23 +5,66 @@
 extern crate changelog;
 extern crate git;
 
+pub mod config;
+pub mod plan;
+pub mod report;
+pub mod workflow;
+
 use std::fmt::{self, Display};
 use std::fs;
-use std::path::PathBuf;
-use std::process::Command;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
 
-use changelog::ChangeLog;
+use changelog::{ChangeLog, Version};
 use chrono::prelude::*;
 use git::{Git, GitCloneUrl};
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+
+pub use workflow::{
+    clone, publish, rebase, snapshot, upstream, CloneOptions, PublishOptions, RebaseOptions,
+    SnapshotOptions, UpstreamOptions,
+};
 
 static GIT_STABLE_BRANCH: &str = "stable";
 
+/// Compressions supported for orig tarballs, by order of preference.
+static ORIG_TARBALL_SUFFIXES: [&str; 4] = ["gz", "xz", "bz2", "lzma"];
+
 #[derive(Debug)]
 pub enum Error {
     VersionError(String),
     ImportError(String, String),
+    ImportVerifyError(String),
     ShowError(),
     BuildError(),
+    BuildsError(Vec<String>),
+    RebasesError(Vec<String>),
+    LintError(String),
+    LintViolationError(String, usize),
+    PublishError(String),
+    UploadError(String),
+    SignatureError(String),
+    SigningKeyError(String),
+    ChecksumError(String, String),
+    OptionError(String),
+    DputHostError(String),
+    LatestVersionError(String),
+    CompressionError(String),
+    SnapshotVersionError(String),
+    ParseVersionError(String),
+    DirtyError(String),
+    NoCheckoutError(String, String),
+    SnapshotTarballError(String, String),
+    OrigTarballError(String),
+    AmbiguousTarballError(String, String),
+    MergeConflict(String, String),
+    ChangeLogError(changelog::Error),
+    GitError(git::Error),
+    ConfigError(String, String),
+    TimeoutError(String),
+    IoError(io::Error),
     Fatal(String),
 }
 
@@ -31,43 +74,285 @@ impl Display for Error {
         match self {
             VersionError(s) => write!(f, "unable to download tarball {}", s),
             ImportError(p, v) => write!(f, "unable to import {} to {}", v, p),
+            ImportVerifyError(s) => write!(
+                f,
+                "gbp import-orig did not create {}, was the version already imported?",
+                s
+            ),
             ShowError() => write!(f, "unable to execute git show process"),
-            BuildError() => write!(f, "unable to execute buildackage process"),
+            BuildError() => write!(f, "unable to execute buildpackage process"),
+            BuildsError(p) => write!(f, "unable to build {}", p.join(", ")),
+            RebasesError(p) => write!(f, "unable to rebase {}", p.join(", ")),
+            LintError(s) => write!(f, "unable to execute lintian on {}", s),
+            LintViolationError(s, n) => write!(f, "lintian reported {} errors on {}", n, s),
+            PublishError(s) => write!(f, "unable to publish package to {}", s),
+            UploadError(s) => write!(f, "unable to upload {}", s),
+            SignatureError(s) => write!(f, "unable to verify signature of {}", s),
+            SigningKeyError(s) => write!(
+                f,
+                "unable to find the upstream signing key of {}, expected in \
+                 debian/upstream/signing-key.asc",
+                s
+            ),
+            ChecksumError(c, e) => write!(f, "checksum mismatch, computed {} expected {}", c, e),
+            OptionError(s) => write!(f, "invalid options, {}", s),
+            DputHostError(s) => write!(f, "dput host {} not found in dput.cf", s),
+            LatestVersionError(s) => write!(f, "uscan found no upstream version for {}", s),
+            CompressionError(s) => write!(f, "unable to recompress {}", s),
+            SnapshotVersionError(s) => write!(
+                f,
+                "{} is not a valid upstream version, it should start with a digit",
+                s
+            ),
+            ParseVersionError(s) => write!(f, "unable to parse version {}", s),
+            DirtyError(s) => write!(f, "{} has local changes, please commit or stash them", s),
+            NoCheckoutError(s, d) => write!(f, "no checkout of {} found in {}", s, d),
+            SnapshotTarballError(n, c) => write!(
+                f,
+                "unable to find the snapshot tarball of {}, tarballs found: [{}]",
+                n, c
+            ),
+            AmbiguousTarballError(s, t) => {
+                write!(
+                    f,
+                    "unable to choose the snapshot tarball of {} among {}",
+                    s, t
+                )
+            }
+            // Already describes the failure (e.g. unable to parse
+            // version: ...).
+            ChangeLogError(s) => write!(f, "{}", s),
+            GitError(s) => write!(f, "{}", s),
+            ConfigError(p, e) => write!(f, "unable to read configuration {}, {}", p, e),
+            MergeConflict(s, c) => write!(
+                f,
+                "merging {} conflicts in {}, please resolve them manually",
+                s, c
+            ),
+            OrigTarballError(s) => write!(f, "unable to find orig tarball {}", s),
+            TimeoutError(s) => write!(f, "command timed out: {}", s),
+            IoError(e) => write!(f, "unexpected error {}", e),
             Fatal(s) => write!(f, "unexpected error {}", s),
         }
     }
 }
 
-impl From<std::io::Error> for Error {
-    fn from(error: std::io::Error) -> Self {
-        Error::Fatal(error.to_string())
+impl Error {
+    /// Returns the exit code of the process failing with this error:
+    /// 2 for versions and tarballs, 3 for git, 4 for build, 5 for
+    /// publish and 1 otherwise.
+    pub fn exit_code(&self) -> i32 {
+        use self::Error::*;
+        match self {
+            VersionError(_)
+            | ImportError(_, _)
+            | ImportVerifyError(_)
+            | SignatureError(_)
+            | SigningKeyError(_)
+            | ChecksumError(_, _)
+            | LatestVersionError(_)
+            | CompressionError(_)
+            | SnapshotVersionError(_)
+            | ParseVersionError(_)
+            | SnapshotTarballError(_, _)
+            | OrigTarballError(_)
+            | AmbiguousTarballError(_, _)
+            | RebasesError(_) => 2,
+            ShowError()
+            | DirtyError(_)
+            | NoCheckoutError(_, _)
+            | MergeConflict(_, _)
+            | GitError(_) => 3,
+            BuildError() | BuildsError(_) | LintError(_) | LintViolationError(_, _) => 4,
+            PublishError(_) | UploadError(_) | DputHostError(_) => 5,
+            OptionError(_)
+            | ConfigError(_, _)
+            | ChangeLogError(_)
+            | TimeoutError(_)
+            | IoError(_)
+            | Fatal(_) => 1,
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::ChangeLogError(e) => Some(e),
+            Error::GitError(e) => Some(e),
+            Error::IoError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::TimedOut => Error::TimeoutError(error.to_string()),
+            _ => Error::IoError(error),
+        }
     }
 }
 
 impl From<git::Error> for Error {
     fn from(error: git::Error) -> Self {
-        Error::Fatal(error.to_string())
+        match error {
+            git::Error::TimeoutError(s) => Error::TimeoutError(s),
+            git::Error::MergeConflict(s, c) => Error::MergeConflict(s, c),
+            _ => Error::GitError(error),
+        }
+    }
+}
+
+impl From<changelog::Error> for Error {
+    fn from(error: changelog::Error) -> Self {
+        match error {
+            changelog::Error::VersionError(s) => Error::ParseVersionError(s),
+            _ => Error::ChangeLogError(error),
+        }
     }
 }
 
 // Let's try to be a bit more concise
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// How `gbp import-orig` integrates an upstream tarball in the
+/// packaging branch.
+///
+/// In all cases the tarball is committed on the `upstream` branch,
+/// tagged `upstream/<version>` and recorded in `pristine-tar`. The
+/// resulting graph of the packaging branch differs:
+///
+/// - `Merge("replace")`: a merge commit is created with the previous
+///   packaging HEAD and the upstream import as parents, its tree is
+///   the upstream sources plus the current debian/ directory.
+/// - `Merge("merge")`: a regular git merge of the upstream import,
+///   which can conflict when upstream files were patched in place.
+/// - `Merge("auto")`: gbp picks one of the above.
+/// - `NoMerge`: the packaging branch is left untouched, merging the
+///   upstream branch is up to the user.
+#[derive(Debug, PartialEq)]
+pub enum ImportMerge {
+    Merge(String),
+    NoMerge,
+}
+
+impl ImportMerge {
+    /// Returns the import strategy, `mode` is only meaningful when
+    /// `merge` is set so asking for both a merge mode and no merge
+    /// is an error.
+    pub fn new(merge: bool, mode: Option<&str>) -> Result<ImportMerge> {
+        match (merge, mode) {
+            (true, Some(mode)) => match mode {
+                "auto" | "merge" | "replace" => Ok(ImportMerge::Merge(mode.to_string())),
+                _ => Err(Error::OptionError(format!("unknown merge mode {}", mode))),
+            },
+            (true, None) => Ok(ImportMerge::Merge("replace".to_string())),
+            (false, Some(mode)) => Err(Error::OptionError(format!(
+                "merge mode {} requested without merging",
+                mode
+            ))),
+            (false, None) => Ok(ImportMerge::NoMerge),
+        }
+    }
+
+    fn args(&self) -> Vec<String> {
+        match self {
+            ImportMerge::Merge(mode) => {
+                vec!["--merge".to_string(), format!("--merge-mode={}", mode)]
+            }
+            ImportMerge::NoMerge => vec!["--no-merge".to_string()],
+        }
+    }
+}
+
+impl Default for ImportMerge {
+    fn default() -> Self {
+        ImportMerge::Merge("replace".to_string())
+    }
+}
+
+/// Compression of the tarballs generated for snapshots, `Keep`
+/// imports them as produced (i.e. gzip).
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum TarballCompression {
+    #[default]
+    Keep,
+    Xz,
+}
+
+impl From<&str> for TarballCompression {
+    fn from(value: &str) -> Self {
+        match value {
+            "xz" => TarballCompression::Xz,
+            _ => TarballCompression::Keep,
+        }
+    }
+}
+
+/// Tool building the binary packages in a clean chroot.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Builder {
+    /// sbuild using the given chroot (e.g. eoan-amd64).
+    Sbuild(String),
+    /// gbp buildpackage --git-pbuilder, the chroot is selected by
+    /// git-pbuilder (i.e. DIST and ARCH).
+    Pbuilder,
+}
+
+/// Options controlling how an upstream release is downloaded,
+/// verified and imported.
+#[derive(Debug, Default)]
+pub struct ImportOptions {
+    pub force_download: bool,
+    pub verify_signature: bool,
+    pub sha256: Option<String>,
+    pub merge: ImportMerge,
+    pub compression: TarballCompression,
+}
+
+/// A source package checked out in `rootdir/name`.
+///
+/// ```
+/// use std::path::{Path, PathBuf};
+/// use uosp::Package;
+///
+/// let pkg = Package::new("nova", PathBuf::from("/srv/packages")).unwrap();
+///
+/// assert_eq!(pkg.name(), "nova");
+/// assert_eq!(pkg.rootdir(), Path::new("/srv/packages"));
+/// assert_eq!(pkg.workdir(), Path::new("/srv/packages/nova"));
+/// assert_eq!(pkg.changelog().workdir, pkg.workdir());
+/// assert!(pkg.git().is_none());
+/// ```
+///
+/// The fields are private, the accessors above are the API:
+///
+/// ```compile_fail
+/// use std::path::PathBuf;
+/// use uosp::Package;
+///
+/// let pkg = Package::new("nova", PathBuf::from("/srv/packages")).unwrap();
+/// let _ = pkg.workdir.clone();
+/// ```
 pub struct Package {
-    pub name: String,
-    pub rootdir: PathBuf,
-    pub workdir: PathBuf,
-    pub changelog: ChangeLog,
-    pub git: Option<Git>,
+    name: String,
+    rootdir: PathBuf,
+    workdir: PathBuf,
+    changelog: ChangeLog,
+    git: Option<Git>,
+    /// Whether `rootdir/build-area` has to be created before to
+    /// build.
+    build_area: bool,
+    /// When set commands are only printed.
+    dry_run: bool,
+    /// When set the output of the commands is prefixed line by line.
+    output_prefix: Option<String>,
 }
 
 impl Package {
     pub fn new(name: &str, rootdir: PathBuf) -> Result<Package> {
-        // TODO(sahid): Do we really need this here?
-        // I should refer gbp.conf
-        let mut builddir = rootdir.clone();
-        builddir.push("build-area");
-        fs::create_dir_all(builddir)?;
         let mut workdir = rootdir.clone();
         workdir.push(name);
         Ok(Package {
@@ -76,23 +361,318 @@ impl Package {
             workdir: workdir.clone(),
             changelog: ChangeLog::new(workdir.clone()),
             git: None,
+            build_area: true,
+            dry_run: false,
+            output_prefix: None,
         })
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the directory containing the checkout.
+    pub fn rootdir(&self) -> &Path {
+        &self.rootdir
+    }
+
+    /// Returns the checkout of the package (i.e. rootdir/name).
+    pub fn workdir(&self) -> &Path {
+        &self.workdir
+    }
+
+    pub fn changelog(&self) -> &ChangeLog {
+        &self.changelog
+    }
+
+    /// Returns the git repository, only available once cloned.
+    pub fn git(&self) -> Option<&Git> {
+        self.git.as_ref()
+    }
+
+    /// Sets whether `rootdir/build-area` has to be created before to
+    /// build.
+    pub fn set_build_area(&mut self, build_area: bool) {
+        self.build_area = build_area;
+    }
+
+    /// Sets the maintainer signing the new changelog entries, instead
+    /// of the one of DEBFULLNAME and DEBEMAIL.
+    pub fn with_maintainer(mut self, name: &str, email: &str) -> Package {
+        self.changelog = self.changelog.with_maintainer(name, email);
+        self
+    }
+
+    /// Forces new releases to create a new changelog entry, even if
+    /// the top one is UNRELEASED.
+    pub fn set_new_stanza(&mut self, new_stanza: bool) {
+        self.changelog.new_stanza = new_stanza;
+    }
+
+    /// Sets the dry-run mode of the package and its changelog.
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+        self.changelog.dry_run = dry_run;
+        if let Some(git) = self.git.as_mut() {
+            git.dry_run = dry_run;
+        }
+    }
+
+    /// Prefixes each line output by the commands with `prefix`, to
+    /// keep the logs of packages handled together readable.
+    pub fn set_output_prefix(&mut self, prefix: Option<String>) {
+        self.output_prefix = prefix;
+    }
+
+    fn run(&self, cmd: &mut Command) -> Result<ExitStatus> {
+        let prefix = match &self.output_prefix {
+            Some(prefix) => Some(prefix.as_str()),
+            None if process::prefix_output() => Some(self.name.as_str()),
+            None => None,
+        };
+        match prefix {
+            Some(prefix) if !self.dry_run => Ok(process::run_with_prefix(cmd, prefix)?),
+            _ => Ok(process::run(cmd, self.dry_run)?),
+        }
+    }
+
+    /// Returns the export-dir configured in debian/gbp.conf if any.
+    pub fn gbp_export_dir(&self) -> Result<Option<String>> {
+        let mut gbpconf = self.workdir.clone();
+        gbpconf.push("debian");
+        gbpconf.push("gbp.conf");
+        if !gbpconf.exists() {
+            return Ok(None);
+        }
+        Ok(Self::parse_gbp_conf(
+            &fs::read_to_string(gbpconf)?,
+            "buildpackage",
+            "export-dir",
+        ))
+    }
+
+    /// Returns the value of `key` in `section` of a gbp.conf, falling
+    /// back to the DEFAULT section as gbp does.
+    fn parse_gbp_conf(content: &str, section: &str, key: &str) -> Option<String> {
+        let mut current = String::new();
+        let mut default = None;
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                current = line[1..line.len() - 1].trim().to_string();
+                continue;
+            }
+            let mut kv = line.splitn(2, '=').map(str::trim);
+            if let (Some(k), Some(v)) = (kv.next(), kv.next()) {
+                if k != key {
+                    continue;
+                }
+                if current == section {
+                    return Some(v.to_string());
+                }
+                if current == "DEFAULT" {
+                    default = Some(v.to_string());
+                }
+            }
+        }
+        default
+    }
+
+    /// Returns the directory where builds are exported, export-dir
+    /// is relative to `workdir` as gbp does.
+    pub fn build_area_dir(&self) -> Result<PathBuf> {
+        Ok(match self.gbp_export_dir()? {
+            Some(dir) => self.workdir.join(dir),
+            None => self.rootdir.join("build-area"),
+        })
+    }
+
+    /// Creates `rootdir/build-area` used to export builds, unless
+    /// disabled or debian/gbp.conf configures its own export-dir.
+    pub fn prepare_build_area(&self) -> Result<()> {
+        if self.dry_run || !self.build_area || self.gbp_export_dir()?.is_some() {
+            return Ok(());
+        }
+        let mut builddir = self.rootdir.clone();
+        builddir.push("build-area");
+        fs::create_dir_all(builddir)?;
+        Ok(())
+    }
+
+    /// Suffixes of the files generated when building or importing a
+    /// package, next to its checkout.
+    const ARTIFACT_SUFFIXES: [&'static str; 6] = [
+        ".dsc",
+        ".changes",
+        ".buildinfo",
+        ".build",
+        ".upload",
+        ".diff.gz",
+    ];
+
+    /// Indicates whether `file` was generated for `name`, e.g.
+    /// nova_20.0.0-0ubuntu1.dsc or its orig and debian tarballs. With
+    /// `any` every file of the package matches, which is used for the
+    /// build-area.
+    fn is_artifact(file: &str, name: &str, any: bool) -> bool {
+        if !file.starts_with(&format!("{}_", name)) {
+            return false;
+        }
+        any || Self::is_tarball(file)
+            || Self::ARTIFACT_SUFFIXES
+                .iter()
+                .any(|suffix| file.ends_with(suffix))
+    }
+
+    /// Removes the files generated for the package in the build-area
+    /// (see `build_area_dir`) and in `rootdir` (orig tarballs, dsc,
+    /// changes...). The checkout itself is never removed.
+    pub fn clean(&self) -> Result<()> {
+        let mut removed = Vec::new();
+        let mut dirs = vec![(self.rootdir.clone(), false)];
+        // The export-dir of gbp.conf is relative to `workdir`, and may
+        // be `rootdir` itself.
+        if let Ok(build_area) = self.build_area_dir()?.canonicalize() {
+            if self.rootdir.canonicalize().ok() != Some(build_area.clone()) {
+                dirs.insert(0, (build_area, true));
+            }
+        }
+        for (dir, any) in &dirs {
+            if !dir.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                let file = path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string();
+                if !Self::is_artifact(&file, &self.name, *any)
+                    || path.starts_with(&self.workdir)
+                    || self.workdir.starts_with(&path)
+                {
+                    continue;
+                }
+                removed.push(path);
+            }
+        }
+        for path in removed {
+            if self.dry_run {
+                println!("[dry-run] rm -r {}", path.display());
+                continue;
+            }
+            info!("Removing {}...", path.display());
+            if path.is_dir() {
+                fs::remove_dir_all(&path)?;
+            } else {
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
     /// Returns a `Package` after to have cloned its repository.
     ///
-    /// By default project will be cloned using ``
-    pub fn clone(name: &str, rootdir: PathBuf, _kind: &str, dist: &str) -> Result<Package> {
+    /// By default project will be cloned using `clone_url`, origin is
+    /// then pointed to the Vcs-Git of debian/control when it differs
+    /// so the repository declared by the package is followed.
+    pub fn clone(
+        name: &str,
+        rootdir: PathBuf,
+        kind: &str,
+        dist: &str,
+        dry_run: bool,
+    ) -> Result<Package> {
+        let url = Self::clone_url(name, kind, dist);
+        let clone_url = url.to_string();
+        let pkg = Self::clone_git(name, rootdir, url, dry_run)?;
+        if let Ok(Some(vcs)) = pkg.vcs_git_url() {
+            let git = pkg.git.as_ref().unwrap();
+            let origin = git.remotes()?.into_iter().find(|(n, _)| n == "origin");
+            if !clone_url.is_empty() && origin.is_some_and(|(_, url)| url != vcs) {
+                info!("Pointing origin to {} as declared by debian/control.", vcs);
+                git.set_remote_url("origin", &vcs)?;
+            }
+        }
+        Ok(pkg)
+    }
+
+    /// Same as `clone` but the repository is cloned from `url`, which
+    /// is kept as origin.
+    pub fn clone_from_url(
+        name: &str,
+        rootdir: PathBuf,
+        url: GitCloneUrl,
+        dry_run: bool,
+    ) -> Result<Package> {
+        let clone_url = url.to_string();
+        let pkg = Self::clone_git(name, rootdir, url, dry_run)?;
+        // Validates the URL used against the one declared by the
+        // package.
+        if let Ok(Some(vcs)) = pkg.vcs_git_url() {
+            if !clone_url.is_empty() && vcs != clone_url {
+                warn!(
+                    "Package cloned from {} but debian/control refers {}.",
+                    clone_url, vcs
+                );
+            }
+        }
+        Ok(pkg)
+    }
+
+    fn clone_git(name: &str, rootdir: PathBuf, url: GitCloneUrl, dry_run: bool) -> Result<Package> {
         let mut pkg = Package::new(name, rootdir)?;
-        let url = if dist == "ubuntu" {
-            GitCloneUrl::UbuntuServerDev(name.to_string())
-        } else {
-            GitCloneUrl::VCSGit
-        };
-        pkg.git = Some(Git::new(&pkg.name, pkg.rootdir.clone(), url)?);
+        pkg.set_dry_run(dry_run);
+        pkg.git = Some(Git::new(&pkg.name, pkg.rootdir.clone(), url, dry_run)?);
         Ok(pkg)
     }
 
+    fn control(&self) -> Result<String> {
+        let mut control = self.workdir.clone();
+        control.push("debian");
+        control.push("control");
+        Ok(fs::read_to_string(control)?)
+    }
+
+    /// Returns the Vcs-Git field of debian/control if any, without
+    /// the optional branch (e.g. -b master).
+    pub fn vcs_git_url(&self) -> Result<Option<String>> {
+        Ok(Self::parse_vcs_git(&self.control()?))
+    }
+
+    fn parse_vcs_git(content: &str) -> Option<String> {
+        content
+            .lines()
+            .find(|l| l.starts_with("Vcs-Git:"))
+            .and_then(|l| l["Vcs-Git:".len()..].split_whitespace().next())
+            .map(str::to_string)
+    }
+
+    /// Returns the URL used to clone the package according to its
+    /// `kind` and `dist`:
+    ///
+    /// | kind              | ubuntu                            | debian |
+    /// |-------------------|-----------------------------------|--------|
+    /// | openstack or auto | ~ubuntu-server-dev/ubuntu/+source | vcsgit |
+    /// | regular           | ubuntu/+source (git-ubuntu)       | vcsgit |
+    ///
+    /// The kind can't be detected before to clone, so auto is
+    /// considered as openstack. On Debian the repositories are spread
+    /// among the team namespaces of salsa, the Vcs-Git of the package
+    /// is used.
+    pub fn clone_url(name: &str, kind: &str, dist: &str) -> GitCloneUrl {
+        match (kind, dist) {
+            ("regular", "ubuntu") => {
+                GitCloneUrl::Plain(format!("https://git.launchpad.net/ubuntu/+source/{}", name))
+            }
+            (_, "ubuntu") => GitCloneUrl::UbuntuServerDev(name.to_string()),
+            _ => GitCloneUrl::VCSGit,
+        }
+    }
+
     /// Returns branch name based on the release.
     /// If the branch name != master returns stable/branch.
     pub fn format_branch(release: &str) -> String {
@@ -108,57 +688,479 @@ impl Package {
         self.workdir.exists()
     }
 
+    /// Indicates whether this `Package` looks like an OpenStack one.
+    ///
+    /// The heuristic checks whether the maintainers or the VCS fields
+    /// of debian/control refer to an OpenStack team.
+    pub fn is_openstack(&self) -> Result<bool> {
+        Ok(Self::is_openstack_control(&self.control()?))
+    }
+
+    fn is_openstack_control(content: &str) -> bool {
+        let fields = [
+            "Maintainer:",
+            "XSBC-Original-Maintainer:",
+            "Uploaders:",
+            "Vcs-Git:",
+            "Vcs-Browser:",
+        ];
+        content
+            .lines()
+            .filter(|l| fields.iter().any(|f| l.starts_with(f)))
+            .any(|l| l.to_lowercase().contains("openstack"))
+    }
+
     /// Downloads upstream release based on the `version`.  The
-    /// tarball will be located at '../'.
-    pub fn download_tarball(&self, version: &str) -> Result<()> {
-        let o = Command::new("uscan")
-            .current_dir(&self.workdir)
+    /// tarball will be located at '../'. When `force` is set an
+    /// already existing tarball is downloaded again.
+    pub fn download_tarball(&self, version: &str, force: bool) -> Result<()> {
+        let o = self.run(&mut self.uscan_command(version, force))?;
+        if !o.success() {
+            return Err(Error::VersionError(version.to_string()));
+        }
+        Ok(())
+    }
+
+    fn uscan_command(&self, version: &str, force: bool) -> Command {
+        let mut cmd = Command::new("uscan");
+        cmd.current_dir(&self.workdir)
             .arg("--download-version")
             .arg(version)
-            .arg("--rename")
-            .status()?;
+            .arg("--rename");
+        if force {
+            cmd.arg("--force-download");
+        }
+        cmd
+    }
+
+    /// Indicates whether `version` is a pre-release or a snapshot
+    /// (e.g. 19.0.0~b1, 19.0.0~git2019061715.86823b5c), which uscan
+    /// is not able to download.
+    pub fn is_snapshot_version(version: &str) -> bool {
+        format!("{}-1", version)
+            .parse::<Version>()
+            .map(|v| v.upstream().contains('~'))
+            .unwrap_or(false)
+    }
+
+    /// Acquires the orig tarball of upstream `version`, downloaded by
+    /// uscan for a release or generated from the `upstream` project
+    /// for a snapshot. Returns the version of the tarball and its
+    /// path.
+    pub fn acquire_tarball(
+        &self,
+        release: &str,
+        version: &str,
+        upstream: Option<&str>,
+    ) -> Result<(String, PathBuf)> {
+        if !Self::is_snapshot_version(version) {
+            self.download_tarball(version, false)?;
+            return Ok((version.to_string(), self.orig_tarball(version)));
+        }
+        self.snapshot_tarball(release, version, upstream, None)
+    }
+
+    /// Generates the tarball of a snapshot of `rev` of the `upstream`
+    /// project, see `generate_snapshot`. Returns the version of the
+    /// tarball and its path.
+    fn snapshot_tarball(
+        &self,
+        release: &str,
+        version: &str,
+        upstream: Option<&str>,
+        rev: Option<&str>,
+    ) -> Result<(String, PathBuf)> {
+        let nameup = upstream.unwrap_or(&self.name);
+        let gitversion = self.generate_snapshot(release, version, upstream, rev)?;
+        let archive = match Self::find_orig_tarball(&self.rootdir, nameup, &gitversion) {
+            Ok(archive) => archive,
+            // Nothing has been generated in dry-run mode.
+            Err(_) if self.dry_run => self
+                .rootdir
+                .join(format!("{}_{}.orig.tar.gz", nameup, gitversion)),
+            Err(e) => return Err(e),
+        };
+        Ok((gitversion, archive))
+    }
+
+    /// Returns the state of the checkout, the newest upstream version
+    /// is only reported when uscan finds one newer than packaged.
+    pub fn status(&self) -> Result<PackageStatus> {
+        let git = self
+            .git
+            .as_ref()
+            .ok_or_else(|| Error::Fatal(format!("package {} is not cloned", self.name)))?;
+        let version = self.changelog.get_head_version().filter(|v| !v.is_empty());
+        let newer_upstream = match (&version, self.latest_upstream_version()) {
+            (Some(current), Ok(latest)) => Self::newer_upstream(current, latest)?,
+            _ => None,
+        };
+        Ok(PackageStatus {
+            branch: git.current_branch()?,
+            version,
+            distribution: self.changelog.get_head_distribution(),
+            clean: git.is_clean()?,
+            newer_upstream,
+        })
+    }
+
+    /// Returns `latest` when newer than the upstream part of the
+    /// packaged version `current`.
+    fn newer_upstream(current: &str, latest: String) -> Result<Option<String>> {
+        let current = current.parse::<Version>()?;
+        let current = format!("{}-0", current.upstream()).parse::<Version>()?;
+        if format!("{}-0", latest).parse::<Version>()? > current {
+            return Ok(Some(latest));
+        }
+        Ok(None)
+    }
+
+    /// Returns the newest upstream version available according to
+    /// `uscan --report`.
+    pub fn latest_upstream_version(&self) -> Result<String> {
+        // Read-only, so executed even in dry-run mode.
+        let o = process::run_output(
+            Command::new("uscan")
+                .current_dir(&self.workdir)
+                .arg("--report"),
+            false,
+        )?;
+        Self::parse_uscan_report(&String::from_utf8_lossy(&o.stdout))
+            .ok_or_else(|| Error::LatestVersionError(self.name.clone()))
+    }
+
+    /// Parses lines like "Newest version of nova on remote site is
+    /// 19.0.1, local version is 19.0.0".
+    fn parse_uscan_report(content: &str) -> Option<String> {
+        let marker = " on remote site is ";
+        content.lines().find_map(|l| {
+            let start = l.find(marker)? + marker.len();
+            let version = l[start..]
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .next()?;
+            if version.is_empty() {
+                None
+            } else {
+                Some(version.to_string())
+            }
+        })
+    }
+
+    /// Returns the path of the orig tarball of `version`, the gzip
+    /// one when it has not been downloaded yet.
+    pub fn orig_tarball(&self, version: &str) -> PathBuf {
+        Self::find_orig_tarball(&self.rootdir, &self.name, version).unwrap_or_else(|_| {
+            self.rootdir
+                .join(format!("{}_{}.orig.tar.gz", self.name, version))
+        })
+    }
+
+    /// Returns the orig tarball of `name` `version` located in
+    /// `rootdir`, whatever its compression.
+    pub fn find_orig_tarball(rootdir: &Path, name: &str, version: &str) -> Result<PathBuf> {
+        ORIG_TARBALL_SUFFIXES
+            .iter()
+            .map(|ext| rootdir.join(format!("{}_{}.orig.tar.{}", name, version, ext)))
+            .find(|archive| archive.exists())
+            .ok_or_else(|| {
+                Error::OrigTarballError(format!(
+                    "{}/{}_{}.orig.tar.*",
+                    rootdir.display(),
+                    name,
+                    version
+                ))
+            })
+    }
+
+    /// Downloads upstream release based on the `version` then
+    /// verifies its signature and checksum when requested by `opts`.
+    /// Returns the path of the verified tarball.
+    pub fn download_and_verify(&self, version: &str, opts: &ImportOptions) -> Result<PathBuf> {
+        self.download_tarball(version, opts.force_download)?;
+        if self.dry_run {
+            // Nothing has been downloaded.
+            return Ok(self.orig_tarball(version));
+        }
+        self.verify_tarball(version, opts)
+    }
+
+    /// Verifies the downloaded tarball of `version` as requested by
+    /// `opts`, returns its path.
+    fn verify_tarball(&self, version: &str, opts: &ImportOptions) -> Result<PathBuf> {
+        let archive = Self::find_orig_tarball(&self.rootdir, &self.name, version)?;
+        if opts.verify_signature {
+            self.verify_signature(&archive)?;
+        }
+        if let Some(sha256) = &opts.sha256 {
+            self.verify_checksum(&archive, sha256)?;
+        }
+        Ok(archive)
+    }
+
+    /// Returns the upstream signing key shipped with the packaging,
+    /// armored or not, as uscan looks for it.
+    fn signing_key(&self) -> Option<PathBuf> {
+        let upstream = self.workdir.join("debian").join("upstream");
+        ["signing-key.asc", "signing-key.pgp"]
+            .iter()
+            .map(|file| upstream.join(file))
+            .find(|path| path.exists())
+    }
+
+    /// Verifies `archive` against the detached signature downloaded
+    /// by uscan next to it. Only the upstream signing key of the
+    /// packaging is trusted, not the keyring of the user.
+    pub fn verify_signature(&self, archive: &Path) -> Result<()> {
+        let signature = PathBuf::from(format!("{}.asc", archive.display()));
+        if !signature.exists() {
+            return Err(Error::SignatureError(archive.display().to_string()));
+        }
+        let key = self
+            .signing_key()
+            .ok_or_else(|| Error::SigningKeyError(self.name.clone()))?;
+        // gpgv expects a binary keyring.
+        let tmpdir = self.private_temp_dir()?;
+        let keyring = tmpdir.join("keyring.gpg");
+        let o = self.run(
+            Command::new("gpg")
+                .arg("--batch")
+                .arg("--yes")
+                .arg("--output")
+                .arg(&keyring)
+                .arg("--dearmor")
+                .arg(&key),
+        )?;
         if !o.success() {
-            return Err(Error::VersionError(version.to_string()));
+            let _ = fs::remove_dir_all(&tmpdir);
+            return Err(Error::SigningKeyError(self.name.clone()));
+        }
+        let o = self.run(
+            Command::new("gpgv")
+                .arg("--keyring")
+                .arg(&keyring)
+                .arg(&signature)
+                .arg(archive),
+        );
+        let _ = fs::remove_dir_all(&tmpdir);
+        if !o?.success() {
+            return Err(Error::SignatureError(archive.display().to_string()));
+        }
+        Ok(())
+    }
+
+    /// Creates a directory in the temporary directory only accessible
+    /// by the user. An existing path is never reused, so its content
+    /// can't be provided by someone else.
+    fn private_temp_dir(&self) -> io::Result<PathBuf> {
+        use std::os::unix::fs::DirBuilderExt;
+        let nanos = Utc::now().timestamp_subsec_nanos();
+        for attempt in 0..8 {
+            let path = std::env::temp_dir().join(format!(
+                "uosp-{}-{}-{}-{}",
+                self.name,
+                std::process::id(),
+                nanos,
+                attempt
+            ));
+            match fs::DirBuilder::new().mode(0o700).create(&path) {
+                Ok(()) => return Ok(path),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "unable to create a private temporary directory",
+        ))
+    }
+
+    /// Verifies the sha256 of `archive` matches `expected_sha256`,
+    /// the comparison is case-insensitive.
+    pub fn verify_checksum(&self, archive: &Path, expected_sha256: &str) -> Result<()> {
+        let mut hasher = Sha256::new();
+        io::copy(&mut fs::File::open(archive)?, &mut hasher)?;
+        let computed = format!("{:x}", hasher.finalize());
+        if !computed.eq_ignore_ascii_case(expected_sha256) {
+            return Err(Error::ChecksumError(computed, expected_sha256.to_string()));
         }
         Ok(())
     }
 
     /// Uses gbp import-orig to apply a tarball downloaded with
     /// `download_tarball` to the package.
-    pub fn apply_tarball(&self, version: &str, archive: &str) -> Result<()> {
-        let o = Command::new("gbp")
-            .current_dir(&self.workdir)
-            .arg("import-orig")
-            .arg("--no-interactive")
-            .arg("--merge-mode=replace")
-            .arg(archive)
-            .status()?;
+    pub fn apply_tarball(&self, version: &str, archive: &str, merge: &ImportMerge) -> Result<()> {
+        let pristine = match &self.git {
+            Some(git) => git.ref_hash("refs/heads/pristine-tar")?,
+            None => None,
+        };
+        let o = self.run(
+            self.gbp()
+                .arg("import-orig")
+                .arg("--no-interactive")
+                .args(merge.args())
+                .arg(archive),
+        )?;
         if !o.success() {
             return Err(Error::ImportError(self.name.clone(), version.to_string()));
         }
+        match &self.git {
+            Some(git) if !self.dry_run => self.verify_import(git, version, pristine)?,
+            _ => (),
+        }
         Ok(())
     }
 
+    /// Returns the tag created by gbp when importing upstream
+    /// `version`.
+    pub fn upstream_tag(version: &str) -> String {
+        format!("upstream/{}", Self::escape_tag_version(version))
+    }
+
+    /// Returns the tag of the Debian release `version`, as created
+    /// by gbp buildpackage --git-tag.
+    pub fn debian_tag(version: &str) -> String {
+        format!("debian/{}", Self::escape_tag_version(version))
+    }
+
+    /// Escapes `version` to be used in a git tag following DEP-14,
+    /// which gbp applies: ':' and '~' are replaced by '%' and '_',
+    /// and sequences git does not allow are broken with '#'.
+    fn escape_tag_version(version: &str) -> String {
+        let mut escaped = version.replace(':', "%").replace('~', "_");
+        while escaped.contains("..") {
+            escaped = escaped.replace("..", ".#.");
+        }
+        if escaped.ends_with('.') {
+            escaped.push('#');
+        }
+        if escaped.ends_with(".lock") {
+            let len = escaped.len();
+            escaped.replace_range(len - ".lock".len().., ".#lock");
+        }
+        escaped
+    }
+
+    /// Tags the last commit as the Debian release `version`.
+    pub fn tag_release(&self, version: &str) -> Result<()> {
+        let git = self
+            .git
+            .as_ref()
+            .ok_or_else(|| Error::Fatal(format!("package {} is not cloned", self.name)))?;
+        git.tag(
+            &Self::debian_tag(version),
+            Some(&format!("{} Debian release {}", self.name, version)),
+        )?;
+        Ok(())
+    }
+
+    /// Verifies gbp import-orig did create the upstream tag and
+    /// updated pristine-tar, which was at `pristine` before import.
+    fn verify_import(&self, git: &Git, version: &str, pristine: Option<String>) -> Result<()> {
+        let tag = Self::upstream_tag(version);
+        if !git.has_tag(&tag)? {
+            return Err(Error::ImportVerifyError(format!("tag {}", tag)));
+        }
+        if pristine.is_some() && git.ref_hash("refs/heads/pristine-tar")? == pristine {
+            return Err(Error::ImportVerifyError(
+                "a new pristine-tar commit".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns a gbp command run in the checkout, gbp runs git so it
+    /// has to be isolated as git is.
+    fn gbp(&self) -> Command {
+        let mut cmd = Command::new("gbp");
+        cmd.current_dir(&self.workdir);
+        git::isolate(&mut cmd);
+        cmd
+    }
+
     /// Uses gbp buildpackage to build `Package`.
     pub fn build(&self) -> Result<()> {
-        Command::new("gbp")
-            .current_dir(&self.workdir)
-            .arg("buildpackage")
-            .arg("-S")
-            .arg("-sa")
-            .arg("-d")
-            .status()?;
+        self.prepare_build_area()?;
+        let o = self.run(
+            self.gbp()
+                .arg("buildpackage")
+                .arg("-S")
+                .arg("-sa")
+                .arg("-d"),
+        )?;
+        if !o.success() {
+            return Err(Error::BuildError());
+        }
+        Ok(())
+    }
+
+    /// Builds the binary packages of `Package` in a clean chroot
+    /// using `builder`, catching failures a source build misses.
+    pub fn build_binary(&self, builder: Builder) -> Result<()> {
+        let mut cmd = match builder {
+            Builder::Sbuild(chroot) => {
+                let mut cmd = Command::new("sbuild");
+                cmd.arg("-d").arg(chroot);
+                cmd
+            }
+            Builder::Pbuilder => {
+                self.prepare_build_area()?;
+                let mut cmd = self.gbp();
+                cmd.arg("buildpackage").arg("--git-pbuilder");
+                cmd
+            }
+        };
+        let o = self.run(cmd.current_dir(&self.workdir))?;
+        if !o.success() {
+            return Err(Error::BuildError());
+        }
         Ok(())
     }
 
+    /// Returns the path of the source changes built for the head
+    /// version, in the build-area.
+    pub fn source_changes(&self) -> Result<PathBuf> {
+        let version = self.changelog.get_head_version().unwrap_or_default();
+        Ok(self
+            .build_area_dir()?
+            .join(format!("{}_{}_source.changes", self.name, version)))
+    }
+
+    /// Runs lintian on the source changes of the last build, found in
+    /// the build-area.
+    pub fn lint(&self) -> Result<LintReport> {
+        let changes = self.source_changes()?;
+        if !self.dry_run && !changes.exists() {
+            return Err(Error::LintError(changes.display().to_string()));
+        }
+        let o = process::run_output(
+            Command::new("lintian")
+                .current_dir(&self.rootdir)
+                .arg(&changes),
+            self.dry_run,
+        )?;
+        let stdout = String::from_utf8_lossy(&o.stdout);
+        print!("{}", stdout);
+        eprint!("{}", String::from_utf8_lossy(&o.stderr));
+        // lintian exits with 1 when it reports errors, anything above
+        // means it did not run.
+        if !matches!(o.status.code(), Some(0) | Some(1)) {
+            return Err(Error::LintError(changes.display().to_string()));
+        }
+        Ok(LintReport::parse(&stdout))
+    }
+
     /// Downloads upstream release, then use pkos-generate-snapshot to
-    /// create tarball. This function returns a githash as tarball
-    /// identifier.
+    /// create tarball. This function returns the version of the
+    /// snapshot, which names the tarball moved in `rootdir`.
+    ///
+    /// The HEAD of the `release` branch is used, unless `rev` pins
+    /// the commit or tag to snapshot.
     pub fn generate_snapshot(
         &self,
         release: &str,
         version: &str,
         upstream: Option<&str>,
+        rev: Option<&str>,
     ) -> Result<String> {
         let branch = Self::format_branch(release);
 
@@ -175,51 +1177,1449 @@ impl Package {
             nameup,
             rootdir,
             GitCloneUrl::OpenStackUpstream(nameup.to_string()),
+            self.dry_run,
         )?;
+        // Refreshes the remote so newly cut stable branches are known.
+        gitupstream.fetch("origin", None)?;
         gitupstream.checkout(&branch)?;
+        if !self.dry_run && gitupstream.current_branch()? != branch {
+            return Err(git::Error::CheckoutError(branch).into());
+        }
         gitupstream.update()?;
-        Command::new("pkgos-generate-snapshot")
-            .current_dir(&gitupstream.workdir)
-            .status()?;
+        // Pins the snapshot rather than using the HEAD of the branch.
+        if let Some(rev) = rev {
+            gitupstream.checkout_detached(rev)?;
+        }
+        self.run(Command::new("pkgos-generate-snapshot").current_dir(&gitupstream.workdir))?;
         let githash = gitupstream.get_hash()?;
-        let gitversion = self.version_from_githash(version, &githash);
-        // The tarball generated is located in '~/tarballs', so let's
+        info!(
+            "Snapshot of {} at {}.",
+            nameup,
+            gitupstream.get_full_hash()?
+        );
+        // Computed once, the version is also the one of the tarball.
+        let gitversion = self.version_from_githash(version, &githash)?;
+        // The tarball generated is expected in '~/tarballs', so let's
         // move it in the package rootdir.
-        Command::new("/bin/sh")
-            .arg("-c")
-            .arg(format!(
-                "mv ~/tarballs/{}_*.orig.tar.gz {}/{}_{}.orig.tar.gz",
-                nameup,
-                self.rootdir.to_str().unwrap(),
+        let mut searched = vec![gitupstream.workdir.clone()];
+        if let Some(home) = dirs::home_dir() {
+            searched.insert(0, home.join("tarballs"));
+        }
+        if self.dry_run {
+            println!(
+                "[dry-run] mv {}/{}_*.orig.tar.* {}",
+                searched[0].display(),
                 nameup,
-                gitversion
-            ))
-            .status()?;
-        Ok(githash)
+                self.rootdir.display()
+            );
+            return Ok(gitversion);
+        }
+        let tarball = Self::find_snapshot_tarball(&searched, nameup)?;
+        // The compression of the generated tarball is kept.
+        let ext = tarball
+            .extension()
+            .map(|e| e.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let target = self
+            .rootdir
+            .join(format!("{}_{}.orig.tar.{}", nameup, gitversion, ext));
+        if fs::rename(&tarball, &target).is_err() {
+            // Retries as a copy, e.g. ~/tarballs on another device.
+            fs::copy(&tarball, &target)?;
+            fs::remove_file(&tarball)?;
+        }
+        Ok(gitversion)
+    }
+
+    /// Returns the most recent tarball of `nameup` found in `dirs`,
+    /// the error lists the tarballs found when none matches or when
+    /// the most recent ones have the same modification time.
+    fn find_snapshot_tarball(dirs: &[PathBuf], nameup: &str) -> Result<PathBuf> {
+        let mut found = Vec::new();
+        for dir in dirs.iter().filter(|d| d.is_dir()) {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if Self::is_tarball(&path.to_string_lossy()) {
+                    found.push(path);
+                }
+            }
+        }
+        let mut candidates: Vec<&PathBuf> = found
+            .iter()
+            .filter(|p| {
+                p.file_name()
+                    .map(|f| Self::is_snapshot_tarball(&f.to_string_lossy(), nameup))
+                    .unwrap_or(false)
+            })
+            .collect();
+        let modified = |p: &PathBuf| fs::metadata(p).and_then(|m| m.modified()).ok();
+        candidates.sort_by_key(|p| modified(p));
+        match candidates.as_slice() {
+            // Several tarballs generated at the same time can't be
+            // told apart.
+            [.., previous, newest] if modified(previous) == modified(newest) => {
+                Err(Error::AmbiguousTarballError(
+                    nameup.to_string(),
+                    candidates
+                        .iter()
+                        .filter(|p| modified(p) == modified(newest))
+                        .map(|p| p.display().to_string())
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                ))
+            }
+            [.., newest] => Ok(newest.to_path_buf()),
+            [] => Err(Error::SnapshotTarballError(
+                nameup.to_string(),
+                found
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+            )),
+        }
+    }
+
+    /// Indicates whether `filename` is a tarball compressed with one
+    /// of the orig tarball compressions.
+    fn is_tarball(filename: &str) -> bool {
+        ORIG_TARBALL_SUFFIXES
+            .iter()
+            .any(|ext| filename.ends_with(&format!(".tar.{}", ext)))
+    }
+
+    /// Indicates whether `filename` is a tarball of `nameup` (e.g.
+    /// nova_1.0.orig.tar.gz or nova-1.0.tar.xz).
+    fn is_snapshot_tarball(filename: &str, nameup: &str) -> bool {
+        Self::is_tarball(filename)
+            && [format!("{}_", nameup), format!("{}-", nameup)]
+                .iter()
+                .any(|prefix| {
+                    filename.starts_with(prefix.as_str())
+                        && filename[prefix.len()..].starts_with(|c: char| c.is_ascii_digit())
+                })
+    }
+
+    /// Returns a summary of the upstream commits between the tags
+    /// `from` and `to`, based on a clone located in './t'.
+    pub fn upstream_log(&self, from: &str, to: &str) -> Result<String> {
+        let mut rootdir = self.rootdir.clone();
+        rootdir.push("t");
+        let gitupstream = Git::new(
+            &self.name,
+            rootdir,
+            GitCloneUrl::OpenStackUpstream(self.name.clone()),
+            self.dry_run,
+        )?;
+        let lines = gitupstream.log_oneline(&Self::upstream_log_range(from, to))?;
+        Ok(Self::format_upstream_log(from, to, &lines))
+    }
+
+    pub fn upstream_log_range(from: &str, to: &str) -> String {
+        format!("{}..{}", from, to)
+    }
+
+    /// Formats `lines` of git log --oneline, only the first ones are
+    /// kept to keep it condensed.
+    pub fn format_upstream_log(from: &str, to: &str, lines: &[String]) -> String {
+        let max = 20;
+        let mut out = format!("Upstream changes from {} to {}:\n", from, to);
+        for line in lines.iter().take(max) {
+            out.push_str(&format!("  * {}\n", line));
+        }
+        if lines.len() > max {
+            out.push_str(&format!("  ... and {} more.\n", lines.len() - max));
+        }
+        out.trim_end().to_string()
+    }
+
+    /// Recompresses `archive` according to `compression`, returns
+    /// the path of the tarball to import.
+    pub fn recompress_tarball(
+        &self,
+        archive: &Path,
+        compression: TarballCompression,
+    ) -> Result<PathBuf> {
+        let target = Self::recompressed_path(archive, compression);
+        if target == archive {
+            return Ok(target);
+        }
+        let (mut decompress, mut compress) = Self::recompress_commands(archive);
+        if !self.dry_run {
+            compress.stdout(fs::File::create(&target)?);
+        }
+        let recompressed = process::run_piped(&mut decompress, &mut compress, self.dry_run);
+        if self.dry_run {
+            return Ok(target);
+        }
+        // The original archive is only removed once both commands
+        // succeeded, a truncated target is removed instead.
+        match recompressed {
+            Ok((d, c)) if d.success() && c.success() => {
+                fs::remove_file(archive)?;
+                Ok(target)
+            }
+            _ => {
+                let _ = fs::remove_file(&target);
+                Err(Error::CompressionError(archive.display().to_string()))
+            }
+        }
+    }
+
+    /// Returns `archive` (e.g. nova_1.0.orig.tar.gz) with the
+    /// extension of `compression`.
+    pub fn recompressed_path(archive: &Path, compression: TarballCompression) -> PathBuf {
+        match compression {
+            TarballCompression::Keep => archive.to_path_buf(),
+            TarballCompression::Xz => archive.with_extension("xz"),
+        }
+    }
+
+    fn recompress_commands(archive: &Path) -> (Command, Command) {
+        let decompress = match archive.extension().and_then(|e| e.to_str()) {
+            Some("bz2") => "bzip2",
+            Some("lzma") => "xz",
+            _ => "gzip",
+        };
+        let mut decompress = Command::new(decompress);
+        decompress.arg("-dc").arg(archive);
+        let mut compress = Command::new("xz");
+        compress.arg("-c");
+        (decompress, compress)
+    }
+
+    /// Returns the version of a snapshot of `version` at `githash`
+    /// taken now, see `version_from_githash_at`.
+    pub fn version_from_githash(&self, version: &str, githash: &str) -> Result<String> {
+        Self::version_from_githash_at(version, githash, Utc::now())
+    }
+
+    /// Returns the version of a snapshot of `version` at `githash`
+    /// taken at `time`, e.g. 19.0.1~git2019061715.abc1234. The time is
+    /// formatted in UTC so the version does not depend on the host.
+    pub fn version_from_githash_at(
+        version: &str,
+        githash: &str,
+        time: DateTime<Utc>,
+    ) -> Result<String> {
+        Self::normalize_upstream_version(&format!(
+            "{}~git{}.{}",
+            version,
+            time.format("%Y%m%d%H"),
+            githash
+        ))
     }
 
-    pub fn version_from_githash(&self, version: &str, githash: &str) -> String {
-        let utc: DateTime<Utc> = Utc::now();
-        format!("{}~git{}.{}", version, utc.format("%Y%m%d%H"), githash)
+    /// Returns the suffix appended by `publish` to the version of the
+    /// backport published at `time` (e.g. ~ppa201906171530).
+    pub fn ppa_suffix(time: DateTime<Utc>) -> String {
+        format!("~ppa{}", time.format("%Y%m%d%H%M"))
     }
 
-    /// Publishing a package in launchpad PPA
-    pub fn publish(&self, ppa: &str, serie: &str, _fake: bool) -> Result<()> {
+    /// Returns `version` as a legal Debian upstream version, the
+    /// characters not allowed are replaced by dots.
+    pub fn normalize_upstream_version(version: &str) -> Result<String> {
+        let normalized: String = version
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '+' | '~' => c,
+                _ => '.',
+            })
+            .collect();
+        // Checks the parser reads it back as an upstream version.
+        let parsed = format!("{}-1", normalized).parse::<Version>();
+        if !normalized.starts_with(|c: char| c.is_ascii_digit())
+            || !matches!(parsed, Ok(v) if v.upstream() == normalized)
+        {
+            return Err(Error::SnapshotVersionError(version.to_string()));
+        }
+        Ok(normalized)
+    }
+
+    /// Returns the hosts configured in the dput configuration files
+    /// (i.e. /etc/dput.cf and ~/.dput.cf).
+    pub fn dput_hosts() -> Result<Vec<String>> {
+        let mut paths = vec![PathBuf::from("/etc/dput.cf")];
+        if let Some(home) = dirs::home_dir() {
+            paths.push(home.join(".dput.cf"));
+        }
+        let mut hosts = Vec::new();
+        for path in paths.iter().filter(|p| p.exists()) {
+            hosts.extend(Self::parse_dput_hosts(&fs::read_to_string(path)?));
+        }
+        Ok(hosts)
+    }
+
+    fn parse_dput_hosts(content: &str) -> Vec<String> {
+        content
+            .lines()
+            .map(|l| l.trim())
+            .filter(|l| l.starts_with('[') && l.ends_with(']'))
+            .map(|l| l[1..l.len() - 1].trim().to_string())
+            .filter(|h| h != "DEFAULT")
+            .collect()
+    }
+
+    /// Publishing a package in launchpad PPA. The upload goes to
+    /// `dput_host` when set, an host of dput.cf, or to `ppa`
+    /// otherwise.
+    pub fn publish(
+        &self,
+        ppa: &str,
+        serie: &str,
+        dput_host: Option<&str>,
+        fake_time: Option<&str>,
+    ) -> Result<Published> {
+        if let Some(host) = dput_host {
+            if !Self::dput_hosts()?.iter().any(|h| h == host) {
+                return Err(Error::DputHostError(host.to_string()));
+            }
+        }
         let version = self.changelog.get_head_version().unwrap();
-        let utc: DateTime<Utc> = Utc::now();
+        let build_area = self.build_area_dir()?;
+        let mut cmd =
+            self.backport_command(ppa, serie, dput_host, fake_time, &build_area, &version)?;
+        let o = process::run_output(&mut cmd, self.dry_run)?;
+        let stdout = String::from_utf8_lossy(&o.stdout);
+        print!("{}", stdout);
+        eprint!("{}", String::from_utf8_lossy(&o.stderr));
+        if !o.status.success() {
+            return Err(Error::PublishError(dput_host.unwrap_or(ppa).to_string()));
+        }
+        Ok(Self::parse_backport_output(&stdout, &build_area))
+    }
+
+    /// Returns the backportpackage command publishing the source
+    /// package `version` found in `build_area`, see `publish`.
+    fn backport_command(
+        &self,
+        ppa: &str,
+        serie: &str,
+        dput_host: Option<&str>,
+        fake_time: Option<&str>,
+        build_area: &Path,
+        version: &str,
+    ) -> Result<Command> {
+        let utc: DateTime<Utc> = match fake_time {
+            Some(time) => Utc.from_utc_datetime(
+                &NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S")
+                    .map_err(|_| Error::OptionError(format!("invalid fake time {}", time)))?,
+            ),
+            None => Utc::now(),
+        };
+        // With a fake time backportpackage runs under faketime, so
+        // the versions and dates generated are reproducible.
+        let mut cmd = match fake_time {
+            Some(time) => {
+                let mut cmd = Command::new("faketime");
+                cmd.env("TZ", "UTC").arg(time).arg("backportpackage");
+                cmd
+            }
+            None => Command::new("backportpackage"),
+        };
         //manila_9.0.0~b1~git2019061715.86823b5c-0ubuntu1.dsc
-        Command::new("backportpackage")
-            .current_dir(&self.rootdir)
+        cmd.current_dir(&self.rootdir)
             .arg("-S")
-            .arg(format!("~ppa{}", utc.format("%Y%m%d%H%M")))
+            .arg(Self::ppa_suffix(utc))
             .arg("-u")
-            .arg(ppa)
+            .arg(dput_host.unwrap_or(ppa))
             .arg("-d")
             .arg(serie)
-            // we should refer d/gbp.conf
+            .arg("-w")
+            .arg(build_area)
             .arg("-y")
-            .arg(format!("build-area/{}_{}.dsc", &self.name, &version))
-            .status()?;
+            .arg(build_area.join(format!("{}_{}.dsc", &self.name, version)));
+        Ok(cmd)
+    }
+
+    /// Uploads the source changes of the last build to `target`, a
+    /// host of dput.cf (e.g. ppa:sahid-ferdjaoui/train or
+    /// ftp-master).
+    pub fn upload(&self, target: &str) -> Result<()> {
+        let changes = self.source_changes()?;
+        if !self.dry_run && !changes.exists() {
+            return Err(Error::UploadError(changes.display().to_string()));
+        }
+        let o = self.run(
+            Command::new("dput")
+                .current_dir(&self.rootdir)
+                .arg(target)
+                .arg(&changes),
+        )?;
+        if !o.success() {
+            return Err(Error::UploadError(target.to_string()));
+        }
         Ok(())
     }
+
+    /// Finds in the output of backportpackage the source package
+    /// produced, which is located in `workdir`.
+    fn parse_backport_output(output: &str, workdir: &Path) -> Published {
+        let mut published = Published::default();
+        for word in output.split_whitespace() {
+            let word = word
+                .trim_start_matches('>')
+                .trim_matches(|c| c == '\'' || c == '"');
+            let file = match Path::new(word).file_name() {
+                Some(file) => workdir.join(file),
+                None => continue,
+            };
+            if word.ends_with("_source.changes") {
+                published.changes = Some(file);
+            } else if word.ends_with(".dsc") && word.contains("~ppa") {
+                // Only the backport has the ~ppa suffix, not the
+                // source package given as input.
+                published.dsc = Some(file);
+            }
+        }
+        published
+    }
+}
+
+/// Overview of the state of a package checkout.
+#[derive(Debug)]
+pub struct PackageStatus {
+    pub branch: String,
+    pub version: Option<String>,
+    pub distribution: Option<String>,
+    pub clean: bool,
+    /// Newest upstream version when newer than the packaged one.
+    pub newer_upstream: Option<String>,
+}
+
+impl Display for PackageStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "branch: {}", self.branch)?;
+        writeln!(f, "version: {}", self.version.as_deref().unwrap_or("-"))?;
+        writeln!(
+            f,
+            "distribution: {}",
+            self.distribution.as_deref().unwrap_or("-")
+        )?;
+        writeln!(f, "clean: {}", if self.clean { "yes" } else { "no" })?;
+        writeln!(
+            f,
+            "newer upstream: {}",
+            self.newer_upstream.as_deref().unwrap_or("-")
+        )
+    }
+}
+
+/// Tags reported by lintian, by severity.
+#[derive(Debug, Default)]
+pub struct LintReport {
+    pub errors: usize,
+    pub warnings: usize,
+    pub info: usize,
+    /// Tags reported, with their severity (e.g. E: nova source:
+    /// tag-name).
+    pub tags: Vec<String>,
+}
+
+impl LintReport {
+    /// Parses the output of lintian, the pedantic, experimental and
+    /// overridden tags as well as the notes are ignored.
+    pub fn parse(output: &str) -> LintReport {
+        let mut report = LintReport::default();
+        for line in output.lines() {
+            match line.get(..3) {
+                Some("E: ") => report.errors += 1,
+                Some("W: ") => report.warnings += 1,
+                Some("I: ") => report.info += 1,
+                _ => continue,
+            }
+            report.tags.push(line.to_string());
+        }
+        report
+    }
+}
+
+impl Display for LintReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "lintian: {} errors, {} warnings, {} info",
+            self.errors, self.warnings, self.info
+        )
+    }
+}
+
+/// Source package produced by a publish.
+#[derive(Debug, Default)]
+pub struct Published {
+    pub changes: Option<PathBuf>,
+    pub dsc: Option<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::process::Stdio;
+
+    #[test]
+    fn version_from_githash_at_fixed_time() {
+        let time = Utc.with_ymd_and_hms(2019, 6, 17, 15, 42, 7).unwrap();
+        assert_eq!(
+            Package::version_from_githash_at("19.0.1", "abc1234", time).unwrap(),
+            "19.0.1~git2019061715.abc1234"
+        );
+        assert_eq!(Package::ppa_suffix(time), "~ppa201906171542");
+    }
+
+    #[test]
+    fn escape_tag_version() {
+        assert_eq!(
+            Package::debian_tag("2:19.0.1-0ubuntu1"),
+            "debian/2%19.0.1-0ubuntu1"
+        );
+        assert_eq!(Package::escape_tag_version("19.0.1~b1-1"), "19.0.1_b1-1");
+        assert_eq!(Package::escape_tag_version("1..0"), "1.#.0");
+        assert_eq!(Package::escape_tag_version("1...0"), "1.#.#.0");
+        assert_eq!(Package::escape_tag_version("1.0."), "1.0.#");
+        assert_eq!(Package::escape_tag_version("1.0.lock"), "1.0.#lock");
+    }
+
+    #[test]
+    fn parse_lintian_output() {
+        let output = "E: nova source: source-is-missing foo.js\n\
+                      W: nova: binary-without-manpage usr/bin/nova-api\n\
+                      W: nova: binary-without-manpage usr/bin/nova-compute\n\
+                      I: nova: spelling-error-in-copyright\n\
+                      P: nova source: package-uses-old-debhelper-compat-version\n\
+                      N: 1 tag overridden\n";
+        let report = LintReport::parse(output);
+        assert_eq!(report.errors, 1);
+        assert_eq!(report.warnings, 2);
+        assert_eq!(report.info, 1);
+        assert_eq!(report.tags.len(), 4);
+        assert_eq!(report.tags[0], "E: nova source: source-is-missing foo.js");
+    }
+
+    #[test]
+    fn parse_gbp_conf() {
+        let conf = "[DEFAULT]\n\
+                    debian-branch = master\n\
+                    export-dir = ../default-area\n\
+                    \n\
+                    # comment = ignored\n\
+                    [buildpackage]\n\
+                    export-dir = ../build-area/\n";
+        assert_eq!(
+            Package::parse_gbp_conf(conf, "buildpackage", "export-dir"),
+            Some("../build-area/".to_string())
+        );
+        assert_eq!(
+            Package::parse_gbp_conf(conf, "import-orig", "export-dir"),
+            Some("../default-area".to_string())
+        );
+        assert_eq!(
+            Package::parse_gbp_conf(conf, "buildpackage", "comment"),
+            None
+        );
+        assert_eq!(
+            Package::parse_gbp_conf("", "buildpackage", "export-dir"),
+            None
+        );
+    }
+
+    #[test]
+    fn changelog_version_error_exit_code() {
+        let e = Error::from(changelog::Error::VersionError("1:".to_string()));
+        assert_eq!(e.to_string(), "unable to parse version 1:");
+        assert_eq!(e.exit_code(), 2);
+        let e = Error::from(changelog::Error::ChangeLogError("oops".to_string()));
+        assert_eq!(e.exit_code(), 1);
+    }
+
+    #[test]
+    fn error_source() {
+        use std::error::Error as _;
+        let e = Error::from(changelog::Error::ChangeLogError("oops".to_string()));
+        assert_eq!(e.source().unwrap().to_string(), e.to_string());
+        let e = Error::from(io::Error::new(io::ErrorKind::NotFound, "gone"));
+        assert_eq!(e.to_string(), "unexpected error gone");
+        assert_eq!(e.source().unwrap().to_string(), "gone");
+        let e = Error::from(io::Error::new(io::ErrorKind::TimedOut, "slow"));
+        assert!(e.source().is_none());
+        assert!(Error::BuildError().source().is_none());
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("uosp-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes `content` to `dir/path`, creating the parents.
+    fn write(dir: &Path, path: &str, content: &str) {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git").current_dir(dir).args(args).status();
+        assert!(status.unwrap().success());
+    }
+
+    const CONTROL: &str = "Source: nova\n\
+                           Maintainer: Ubuntu Developers <ubuntu-devel-discuss@lists.ubuntu.com>\n\
+                           Vcs-Git: https://git.launchpad.net/~ubuntu-openstack-dev/ubuntu/+source/nova -b master\n\
+                           \n\
+                           Package: nova-common\n";
+
+    #[test]
+    fn parse_vcs_git() {
+        assert_eq!(
+            Package::parse_vcs_git(CONTROL).as_deref(),
+            Some("https://git.launchpad.net/~ubuntu-openstack-dev/ubuntu/+source/nova")
+        );
+        assert_eq!(
+            Package::parse_vcs_git("Source: nova\nMaintainer: Ubuntu Developers\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn clone_follows_vcs_git() {
+        let rootdir = temp_dir("vcs-git");
+        let workdir = rootdir.join("nova");
+        fs::create_dir_all(&workdir).unwrap();
+        git(&workdir, &["init", "-q"]);
+        git(
+            &workdir,
+            &["remote", "add", "origin", "https://example.com/nova"],
+        );
+        write(&workdir, "debian/control", CONTROL);
+
+        let origin = |pkg: &Package| pkg.git().unwrap().remotes().unwrap()[0].1.clone();
+        let url = GitCloneUrl::Plain("https://example.com/nova".to_string());
+        let pkg = Package::clone_from_url("nova", rootdir.clone(), url, false).unwrap();
+        assert_eq!(origin(&pkg), "https://example.com/nova");
+        let pkg = Package::clone("nova", rootdir.clone(), "openstack", "ubuntu", false).unwrap();
+        assert_eq!(
+            origin(&pkg),
+            "https://git.launchpad.net/~ubuntu-openstack-dev/ubuntu/+source/nova"
+        );
+
+        // Nothing to follow without the field.
+        git(
+            &workdir,
+            &["remote", "set-url", "origin", "https://example.com/nova"],
+        );
+        write(&workdir, "debian/control", "Source: nova\n");
+        let pkg = Package::clone("nova", rootdir.clone(), "openstack", "ubuntu", false).unwrap();
+        assert_eq!(origin(&pkg), "https://example.com/nova");
+        fs::remove_dir_all(&rootdir).unwrap();
+    }
+
+    fn gzip(path: &Path, content: &[u8]) {
+        let mut child = Command::new("gzip")
+            .arg("-c")
+            .stdin(Stdio::piped())
+            .stdout(fs::File::create(path).unwrap())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(content).unwrap();
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[test]
+    fn gbp_isolated() {
+        let package = Package::new("foo", PathBuf::from("/tmp")).unwrap();
+        git::set_isolated(true);
+        let cmd = package.gbp();
+        git::set_isolated(false);
+        assert_eq!(cmd.get_current_dir(), Some(Path::new("/tmp/foo")));
+        let envs: Vec<_> = cmd.get_envs().collect();
+        for (key, value) in git::isolation_env() {
+            assert!(envs.contains(&(key.as_ref(), Some(value.as_ref()))));
+        }
+    }
+
+    #[test]
+    fn recompressed_path() {
+        let archive = Path::new("foo_1.0.orig.tar.gz");
+        assert_eq!(
+            Package::recompressed_path(archive, TarballCompression::Keep),
+            archive
+        );
+        assert_eq!(
+            Package::recompressed_path(archive, TarballCompression::Xz),
+            Path::new("foo_1.0.orig.tar.xz")
+        );
+        assert_eq!(
+            Package::recompressed_path(Path::new("foo_1.0.orig.tar.bz2"), TarballCompression::Xz),
+            Path::new("foo_1.0.orig.tar.xz")
+        );
+    }
+
+    #[test]
+    fn recompress_commands() {
+        for (archive, program) in &[
+            ("it's.tar.gz", "gzip"),
+            ("foo.tar.bz2", "bzip2"),
+            ("foo.tar.lzma", "xz"),
+        ] {
+            let (decompress, compress) = Package::recompress_commands(Path::new(archive));
+            assert_eq!(decompress.get_program(), *program);
+            assert_eq!(
+                decompress.get_args().collect::<Vec<_>>(),
+                vec!["-dc", archive]
+            );
+            assert_eq!(compress.get_program(), "xz");
+            assert_eq!(compress.get_args().collect::<Vec<_>>(), vec!["-c"]);
+        }
+    }
+
+    #[test]
+    fn recompress_tarball() {
+        let dir = temp_dir("recompress");
+        let archive = dir.join("foo_1.0.orig.tar.gz");
+        gzip(&archive, b"content");
+        let package = Package::new("foo", dir.clone()).unwrap();
+        let target = package
+            .recompress_tarball(&archive, TarballCompression::Xz)
+            .unwrap();
+        assert_eq!(target, dir.join("foo_1.0.orig.tar.xz"));
+        assert!(!archive.exists());
+        let output = Command::new("xz").arg("-dc").arg(&target).output().unwrap();
+        assert_eq!(output.stdout, b"content");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recompress_corrupted_tarball() {
+        let dir = temp_dir("recompress-corrupted");
+        let archive = dir.join("foo_1.0.orig.tar.gz");
+        fs::write(&archive, b"not gzip").unwrap();
+        let package = Package::new("foo", dir.clone()).unwrap();
+        match package.recompress_tarball(&archive, TarballCompression::Xz) {
+            Err(Error::CompressionError(_)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(archive.exists());
+        assert!(!dir.join("foo_1.0.orig.tar.xz").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn upstream_log_range() {
+        assert_eq!(
+            Package::upstream_log_range("19.0.0", "19.0.1"),
+            "19.0.0..19.0.1"
+        );
+    }
+
+    #[test]
+    fn format_upstream_log() {
+        let lines: Vec<String> = (0..22).map(|i| format!("{:07x} Change {}", i, i)).collect();
+        assert_eq!(
+            Package::format_upstream_log("19.0.0", "19.0.1", &lines[..2]),
+            "Upstream changes from 19.0.0 to 19.0.1:\n  \
+             * 0000000 Change 0\n  \
+             * 0000001 Change 1"
+        );
+        let log = Package::format_upstream_log("19.0.0", "19.0.1", &lines);
+        assert_eq!(log.lines().count(), 22);
+        assert!(log.contains("  * 0000013 Change 19\n"));
+        assert!(!log.contains("Change 20"));
+        assert!(log.ends_with("  ... and 2 more."));
+        assert_eq!(
+            Package::format_upstream_log("19.0.0", "19.0.1", &[]),
+            "Upstream changes from 19.0.0 to 19.0.1:"
+        );
+    }
+
+    #[test]
+    fn parse_backport_output() {
+        let workdir = Path::new("/tmp/build-area");
+        let output = "dpkg-source: info: extracting manila in manila-9.0.0\n\
+                      dpkg-source: info: building manila in \
+                      manila_9.0.0-0ubuntu1~ppa201906171530.dsc\n\
+                      signfile '/tmp/uosp/manila_9.0.0-0ubuntu1~ppa201906171530.dsc'\n \
+                      dpkg-genchanges  -S >../manila_9.0.0-0ubuntu1~ppa201906171530_source.changes\n\
+                      Uploading manila_9.0.0-0ubuntu1.dsc\n";
+        let published = Package::parse_backport_output(output, workdir);
+        assert_eq!(
+            published.dsc.unwrap(),
+            workdir.join("manila_9.0.0-0ubuntu1~ppa201906171530.dsc")
+        );
+        assert_eq!(
+            published.changes.unwrap(),
+            workdir.join("manila_9.0.0-0ubuntu1~ppa201906171530_source.changes")
+        );
+
+        let published =
+            Package::parse_backport_output("Uploading manila_9.0.0-0ubuntu1.dsc", workdir);
+        assert!(published.dsc.is_none() && published.changes.is_none());
+    }
+
+    #[test]
+    fn parse_uscan_report() {
+        let report =
+            "uscan: Newest version of nova on remote site is 19.0.1, local version is 19.0.0\n\
+                      uscan:  => Newer package available from:\n";
+        assert_eq!(
+            Package::parse_uscan_report(report).as_deref(),
+            Some("19.0.1")
+        );
+        assert_eq!(
+            Package::parse_uscan_report("Newest version of nova on remote site is 19.0.0\n")
+                .as_deref(),
+            Some("19.0.0")
+        );
+        assert_eq!(
+            Package::parse_uscan_report("uscan: no matching files for watch line\n"),
+            None
+        );
+        assert_eq!(
+            Package::parse_uscan_report("Newest version of nova on remote site is , local\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn latest_upstream_version_not_found() {
+        // An empty workdir has no debian/watch, uscan reports nothing
+        // (or is not installed at all).
+        let rootdir = temp_dir("uscan");
+        fs::create_dir_all(rootdir.join("nova")).unwrap();
+        let pkg = Package::new("nova", rootdir.clone()).unwrap();
+        match pkg.latest_upstream_version() {
+            Err(Error::LatestVersionError(name)) => assert_eq!(name, "nova"),
+            Err(Error::IoError(e)) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+            other => panic!("unexpected {:?}", other),
+        }
+        fs::remove_dir_all(&rootdir).unwrap();
+    }
+
+    #[test]
+    fn is_snapshot_tarball() {
+        for filename in &[
+            "nova_19.0.1~git2019061715.abc1234.orig.tar.gz",
+            "nova-19.0.1.dev12.tar.gz",
+            "nova-19.0.1.tar.xz",
+            "nova_19.0.1.orig.tar.bz2",
+            "nova-19.0.1.tar.lzma",
+        ] {
+            assert!(
+                Package::is_snapshot_tarball(filename, "nova"),
+                "{}",
+                filename
+            );
+        }
+        for filename in &[
+            "nova-19.0.1.zip",
+            "nova-19.0.1.tar",
+            "nova-19.0.1.tar.gz.asc",
+            "nova-client-1.0.tar.gz",
+            "python-nova-19.0.1.tar.gz",
+            "nova-.tar.gz",
+            "novaclient-1.0.tar.gz",
+        ] {
+            assert!(
+                !Package::is_snapshot_tarball(filename, "nova"),
+                "{}",
+                filename
+            );
+        }
+    }
+
+    #[test]
+    fn find_snapshot_tarball_in_dirs() {
+        let rootdir = temp_dir("snapshot-dirs");
+        write(&rootdir, "dist/nova-client-1.0.tar.gz", "");
+        write(&rootdir, "dist/README", "");
+        write(&rootdir, "sdist/nova-19.0.1.dev12.tar.gz", "");
+        let dirs = [
+            rootdir.join("missing"),
+            rootdir.join("dist"),
+            rootdir.join("sdist"),
+        ];
+        assert_eq!(
+            Package::find_snapshot_tarball(&dirs, "nova").unwrap(),
+            rootdir.join("sdist/nova-19.0.1.dev12.tar.gz")
+        );
+        fs::remove_dir_all(&rootdir).unwrap();
+    }
+
+    #[test]
+    fn find_orig_tarball() {
+        let rootdir = temp_dir("orig-tarball");
+        write(&rootdir, "nova_19.0.1.orig.tar.xz", "");
+        write(&rootdir, "nova_19.0.0.orig.tar.gz", "");
+        assert_eq!(
+            Package::find_orig_tarball(&rootdir, "nova", "19.0.1").unwrap(),
+            rootdir.join("nova_19.0.1.orig.tar.xz")
+        );
+        match Package::find_orig_tarball(&rootdir, "nova", "19.0.2") {
+            Err(Error::OrigTarballError(s)) => assert!(s.ends_with("nova_19.0.2.orig.tar.*")),
+            other => panic!("unexpected {:?}", other),
+        }
+        // The gzip one is expected when nothing is downloaded.
+        let pkg = Package::new("nova", rootdir.clone()).unwrap();
+        assert_eq!(
+            pkg.orig_tarball("19.0.1"),
+            rootdir.join("nova_19.0.1.orig.tar.xz")
+        );
+        assert_eq!(
+            pkg.orig_tarball("19.0.2"),
+            rootdir.join("nova_19.0.2.orig.tar.gz")
+        );
+        fs::remove_dir_all(&rootdir).unwrap();
+    }
+
+    #[test]
+    fn find_snapshot_tarball_newest() {
+        use std::time::{Duration, SystemTime};
+        let rootdir = temp_dir("snapshot-newest");
+        let touch = |name: &str, secs: u64| {
+            write(&rootdir, name, "");
+            let time = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+            fs::File::options()
+                .write(true)
+                .open(rootdir.join(name))
+                .unwrap()
+                .set_modified(time)
+                .unwrap();
+        };
+        let dirs = [rootdir.clone()];
+        touch("glance-1.0.tar.gz", 3000);
+        match Package::find_snapshot_tarball(&dirs, "nova") {
+            Err(Error::SnapshotTarballError(name, found)) => {
+                assert_eq!(name, "nova");
+                assert_eq!(
+                    found,
+                    rootdir.join("glance-1.0.tar.gz").display().to_string()
+                );
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        touch("nova-19.0.1.dev1.tar.gz", 1000);
+        touch("nova-19.0.1.dev2.tar.gz", 2000);
+        assert_eq!(
+            Package::find_snapshot_tarball(&dirs, "nova").unwrap(),
+            rootdir.join("nova-19.0.1.dev2.tar.gz")
+        );
+
+        touch("nova-19.0.1.dev3.tar.gz", 2000);
+        match Package::find_snapshot_tarball(&dirs, "nova") {
+            Err(Error::AmbiguousTarballError(_, tarballs)) => {
+                assert!(tarballs.contains("dev2") && tarballs.contains("dev3"));
+                assert!(!tarballs.contains("dev1"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        fs::remove_dir_all(&rootdir).unwrap();
+    }
+
+    #[test]
+    fn verify_checksum() {
+        let rootdir = temp_dir("checksum");
+        write(&rootdir, "nova_19.0.1.orig.tar.gz", "content");
+        let archive = rootdir.join("nova_19.0.1.orig.tar.gz");
+        let pkg = Package::new("nova", rootdir.clone()).unwrap();
+        let sha256 = "ed7002b439e9ac845f22357d822bac1444730fbdb6016d3ec9432297b9ec9f73";
+        pkg.verify_checksum(&archive, sha256).unwrap();
+        pkg.verify_checksum(&archive, &sha256.to_uppercase())
+            .unwrap();
+        match pkg.verify_checksum(&archive, &"0".repeat(64)) {
+            Err(Error::ChecksumError(computed, expected)) => {
+                assert_eq!(computed, sha256);
+                assert_eq!(expected, "0".repeat(64));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        fs::remove_dir_all(&rootdir).unwrap();
+    }
+
+    /// Runs gpg with the keys kept in `home`.
+    fn gpg(home: &Path, args: &[&str]) {
+        let status = Command::new("gpg")
+            .arg("--homedir")
+            .arg(home)
+            .arg("--batch")
+            .args(args)
+            .stderr(Stdio::null())
+            .status();
+        assert!(status.unwrap().success());
+    }
+
+    #[test]
+    fn verify_tarball() {
+        let rootdir = temp_dir("verify");
+        let home = rootdir.join("gnupg");
+        fs::create_dir_all(&home).unwrap();
+        gpg(
+            &home,
+            &["--passphrase", "", "--quick-gen-key", "uosp", "ed25519"],
+        );
+        write(&rootdir, "nova_19.0.1.orig.tar.gz", "content");
+        let archive = rootdir.join("nova_19.0.1.orig.tar.gz");
+        let signature = rootdir.join("nova_19.0.1.orig.tar.gz.asc");
+        let key = rootdir.join("nova/debian/upstream/signing-key.asc");
+        fs::create_dir_all(key.parent().unwrap()).unwrap();
+        let (signed, archived) = (signature.to_str().unwrap(), archive.to_str().unwrap());
+        gpg(&home, &["--armor", "--detach-sign", "-o", signed, archived]);
+        gpg(&home, &["--armor", "--export", "-o", key.to_str().unwrap()]);
+        let _ = Command::new("gpgconf")
+            .arg("--homedir")
+            .arg(&home)
+            .args(["--kill", "gpg-agent"])
+            .status();
+
+        let pkg = Package::new("nova", rootdir.clone()).unwrap();
+        let opts = ImportOptions {
+            verify_signature: true,
+            sha256: Some(
+                "ed7002b439e9ac845f22357d822bac1444730fbdb6016d3ec9432297b9ec9f73".to_string(),
+            ),
+            ..ImportOptions::default()
+        };
+        assert_eq!(pkg.verify_tarball("19.0.1", &opts).unwrap(), archive);
+
+        let wrong = ImportOptions {
+            sha256: Some("0".repeat(64)),
+            ..ImportOptions::default()
+        };
+        assert!(matches!(
+            pkg.verify_tarball("19.0.1", &wrong),
+            Err(Error::ChecksumError(_, _))
+        ));
+        assert!(matches!(
+            pkg.verify_tarball("19.0.2", &opts),
+            Err(Error::OrigTarballError(_))
+        ));
+
+        fs::write(&archive, "tampered").unwrap();
+        assert!(matches!(
+            pkg.verify_tarball("19.0.1", &opts),
+            Err(Error::SignatureError(_))
+        ));
+        fs::remove_file(&key).unwrap();
+        assert!(matches!(
+            pkg.verify_tarball("19.0.1", &opts),
+            Err(Error::SigningKeyError(_))
+        ));
+        fs::remove_file(&signature).unwrap();
+        assert!(matches!(
+            pkg.verify_tarball("19.0.1", &opts),
+            Err(Error::SignatureError(_))
+        ));
+        fs::remove_dir_all(&rootdir).unwrap();
+    }
+
+    #[test]
+    fn clean() {
+        let rootdir = temp_dir("clean");
+        let removed = [
+            "nova_19.0.1.orig.tar.gz",
+            "nova_19.0.1-0ubuntu1.dsc",
+            "nova_19.0.1-0ubuntu1_source.changes",
+            "build-area/nova_19.0.1-0ubuntu1.debian.tar.xz",
+            "build-area/nova_19.0.1-0ubuntu1_source.ppa.upload",
+        ];
+        let kept = [
+            "nova/debian/control",
+            "nova/nova_19.0.1.orig.tar.gz",
+            "nova_notes.txt",
+            "novaclient_1.0.orig.tar.gz",
+            "glance_19.0.1-0ubuntu1.dsc",
+            "build-area/glance_19.0.1-0ubuntu1.dsc",
+        ];
+        for path in removed.iter().chain(kept.iter()) {
+            write(&rootdir, path, "");
+        }
+        let mut pkg = Package::new("nova", rootdir.clone()).unwrap();
+        pkg.set_dry_run(true);
+        pkg.clean().unwrap();
+        assert!(removed.iter().all(|path| rootdir.join(path).exists()));
+        pkg.set_dry_run(false);
+        pkg.clean().unwrap();
+        for path in &removed {
+            assert!(!rootdir.join(path).exists(), "{}", path);
+        }
+        for path in &kept {
+            assert!(rootdir.join(path).exists(), "{}", path);
+        }
+        fs::remove_dir_all(&rootdir).unwrap();
+    }
+
+    #[test]
+    fn prepare_build_area() {
+        let rootdir = temp_dir("build-area");
+        fs::create_dir_all(rootdir.join("nova")).unwrap();
+        let mut pkg = Package::new("nova", rootdir.clone()).unwrap();
+
+        pkg.set_dry_run(true);
+        pkg.prepare_build_area().unwrap();
+        assert!(!rootdir.join("build-area").exists());
+        pkg.set_dry_run(false);
+        pkg.set_build_area(false);
+        pkg.prepare_build_area().unwrap();
+        assert!(!rootdir.join("build-area").exists());
+
+        // gbp exports to its own directory.
+        pkg.set_build_area(true);
+        write(
+            &rootdir,
+            "nova/debian/gbp.conf",
+            "[buildpackage]\nexport-dir = ../out\n",
+        );
+        pkg.prepare_build_area().unwrap();
+        assert!(!rootdir.join("build-area").exists());
+
+        fs::remove_file(rootdir.join("nova/debian/gbp.conf")).unwrap();
+        pkg.prepare_build_area().unwrap();
+        assert!(rootdir.join("build-area").is_dir());
+        fs::remove_dir_all(&rootdir).unwrap();
+    }
+
+    fn args(cmd: &Command) -> Vec<String> {
+        cmd.get_args()
+            .map(|a| a.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn backport_command_fake_time() {
+        let pkg = Package::new("manila", PathBuf::from("/tmp")).unwrap();
+        let build_area = Path::new("/tmp/build-area");
+        let cmd = pkg
+            .backport_command(
+                "ppa:sahid-ferdjaoui/train",
+                "bionic",
+                None,
+                Some("2019-06-17 15:30:00"),
+                build_area,
+                "9.0.0-0ubuntu1",
+            )
+            .unwrap();
+        assert_eq!(cmd.get_program(), "faketime");
+        assert!(cmd
+            .get_envs()
+            .any(|e| e == ("TZ".as_ref(), Some("UTC".as_ref()))));
+        assert_eq!(
+            args(&cmd),
+            [
+                "2019-06-17 15:30:00",
+                "backportpackage",
+                "-S",
+                "~ppa201906171530",
+                "-u",
+                "ppa:sahid-ferdjaoui/train",
+                "-d",
+                "bionic",
+                "-w",
+                "/tmp/build-area",
+                "-y",
+                "/tmp/build-area/manila_9.0.0-0ubuntu1.dsc",
+            ]
+        );
+
+        let cmd = pkg
+            .backport_command(
+                "ppa:sahid-ferdjaoui/train",
+                "bionic",
+                None,
+                None,
+                build_area,
+                "9.0.0-0ubuntu1",
+            )
+            .unwrap();
+        assert_eq!(cmd.get_program(), "backportpackage");
+        assert_eq!(cmd.get_envs().count(), 0);
+        assert_eq!(args(&cmd)[0], "-S");
+        assert!(args(&cmd)[1].starts_with("~ppa"));
+
+        assert!(matches!(
+            pkg.backport_command(
+                "ppa:x/y",
+                "bionic",
+                None,
+                Some("yesterday"),
+                build_area,
+                "1-1"
+            ),
+            Err(Error::OptionError(_))
+        ));
+    }
+
+    #[test]
+    fn backport_command_dput_host() {
+        let pkg = Package::new("manila", PathBuf::from("/tmp")).unwrap();
+        let cmd = pkg
+            .backport_command(
+                "ppa:sahid-ferdjaoui/train",
+                "bionic",
+                Some("uosp-ppa"),
+                None,
+                Path::new("/tmp/build-area"),
+                "9.0.0-0ubuntu1",
+            )
+            .unwrap();
+        let args = args(&cmd);
+        let upload = args.iter().position(|a| a == "-u").unwrap();
+        assert_eq!(args[upload + 1], "uosp-ppa");
+        assert!(!args.contains(&"ppa:sahid-ferdjaoui/train".to_string()));
+    }
+
+    #[test]
+    fn publish_unknown_dput_host() {
+        let pkg = Package::new("manila", PathBuf::from("/tmp")).unwrap();
+        match pkg.publish("ppa:x/y", "bionic", Some("uosp-no-such-host"), None) {
+            Err(Error::DputHostError(host)) => assert_eq!(host, "uosp-no-such-host"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_dput_hosts() {
+        let content = "[DEFAULT]\nmethod = ftp\n\n[ uosp-ppa ]\nfqdn = ppa.launchpad.net\n\
+                       incoming = ~sahid-ferdjaoui/ubuntu/train\n[ftp-master]\n";
+        assert_eq!(
+            Package::parse_dput_hosts(content),
+            ["uosp-ppa", "ftp-master"]
+        );
+    }
+
+    #[test]
+    fn clone_url() {
+        let ubuntu_server_dev = GitCloneUrl::UbuntuServerDev("nova".to_string());
+        let git_ubuntu =
+            GitCloneUrl::Plain("https://git.launchpad.net/ubuntu/+source/nova".to_string());
+        for (kind, dist, url) in &[
+            ("openstack", "ubuntu", &ubuntu_server_dev),
+            ("auto", "ubuntu", &ubuntu_server_dev),
+            ("regular", "ubuntu", &git_ubuntu),
+            ("openstack", "debian", &GitCloneUrl::VCSGit),
+            ("auto", "debian", &GitCloneUrl::VCSGit),
+            ("regular", "debian", &GitCloneUrl::VCSGit),
+        ] {
+            assert_eq!(
+                &&Package::clone_url("nova", kind, dist),
+                url,
+                "{} {}",
+                kind,
+                dist
+            );
+        }
+    }
+
+    #[test]
+    fn is_openstack() {
+        let rootdir = temp_dir("is-openstack");
+        let pkg = Package::new("nova", rootdir.clone()).unwrap();
+        assert!(pkg.is_openstack().is_err());
+        for (control, expected) in &[
+            (CONTROL, true),
+            (
+                "Source: python-oslo.config\n\
+                 Maintainer: Ubuntu Developers <ubuntu-devel-discuss@lists.ubuntu.com>\n\
+                 XSBC-Original-Maintainer: Debian OpenStack <team+openstack@tracker.debian.org>\n",
+                true,
+            ),
+            (
+                "Source: python-os-brick\n\
+                 Uploaders: Thomas Goirand <zigo@debian.org>\n\
+                 Vcs-Browser: https://salsa.debian.org/openstack-team/libs/python-os-brick\n",
+                true,
+            ),
+            (
+                "Source: libvirt\n\
+                 Maintainer: Ubuntu Developers <ubuntu-devel-discuss@lists.ubuntu.com>\n\
+                 Vcs-Git: https://git.launchpad.net/ubuntu/+source/libvirt\n\
+                 Description: mentions openstack in the description\n",
+                false,
+            ),
+        ] {
+            write(&rootdir, "nova/debian/control", control);
+            assert_eq!(pkg.is_openstack().unwrap(), *expected, "{}", control);
+        }
+        fs::remove_dir_all(&rootdir).unwrap();
+    }
+
+    #[test]
+    fn uscan_command() {
+        let pkg = Package::new("nova", PathBuf::from("/tmp")).unwrap();
+        let cmd = pkg.uscan_command("19.0.1", false);
+        assert_eq!(cmd.get_program(), "uscan");
+        assert_eq!(cmd.get_current_dir(), Some(Path::new("/tmp/nova")));
+        assert_eq!(args(&cmd), ["--download-version", "19.0.1", "--rename"]);
+        let cmd = pkg.uscan_command("19.0.1", true);
+        assert_eq!(
+            args(&cmd),
+            [
+                "--download-version",
+                "19.0.1",
+                "--rename",
+                "--force-download"
+            ]
+        );
+    }
+
+    #[test]
+    fn import_merge() {
+        let merge = |mode: &str| ImportMerge::Merge(mode.to_string());
+        assert_eq!(ImportMerge::new(true, None).unwrap(), merge("replace"));
+        assert_eq!(ImportMerge::default(), merge("replace"));
+        for mode in &["auto", "merge", "replace"] {
+            assert_eq!(ImportMerge::new(true, Some(mode)).unwrap(), merge(mode));
+        }
+        assert_eq!(ImportMerge::new(false, None).unwrap(), ImportMerge::NoMerge);
+        match ImportMerge::new(true, Some("squash")) {
+            Err(Error::OptionError(e)) => assert_eq!(e, "unknown merge mode squash"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match ImportMerge::new(false, Some("merge")) {
+            Err(Error::OptionError(e)) => {
+                assert_eq!(e, "merge mode merge requested without merging")
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(merge("auto").args(), ["--merge", "--merge-mode=auto"]);
+        assert_eq!(ImportMerge::NoMerge.args(), ["--no-merge"]);
+    }
+
+    /// Returns the package `name` whose checkout in `rootdir` is a
+    /// git repository with an initial commit.
+    fn checkout(rootdir: &Path, name: &str) -> Package {
+        let workdir = rootdir.join(name);
+        fs::create_dir_all(&workdir).unwrap();
+        git(&workdir, &["init", "-q"]);
+        commit(&workdir, "init");
+        let url = GitCloneUrl::Plain(format!("https://example.com/{}", name));
+        Package::clone_from_url(name, rootdir.to_path_buf(), url, false).unwrap()
+    }
+
+    fn commit(workdir: &Path, subject: &str) {
+        git(
+            workdir,
+            &[
+                "-c",
+                "user.name=uosp",
+                "-c",
+                "user.email=uosp@localhost",
+                "commit",
+                "-q",
+                "-a",
+                "--allow-empty",
+                "-m",
+                subject,
+            ],
+        );
+    }
+
+    #[test]
+    fn verify_import() {
+        let rootdir = temp_dir("verify-import");
+        let pkg = checkout(&rootdir, "nova");
+        let workdir = rootdir.join("nova");
+        let git = pkg.git().unwrap();
+        match pkg.verify_import(git, "19.0.1~b1", None) {
+            Err(Error::ImportVerifyError(e)) => assert_eq!(e, "tag upstream/19.0.1_b1"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        self::git(&workdir, &["tag", "upstream/19.0.1_b1"]);
+        pkg.verify_import(git, "19.0.1~b1", None).unwrap();
+
+        // The import is expected to commit on pristine-tar.
+        self::git(&workdir, &["branch", "pristine-tar"]);
+        let pristine = git.ref_hash("refs/heads/pristine-tar").unwrap();
+        match pkg.verify_import(git, "19.0.1~b1", pristine.clone()) {
+            Err(Error::ImportVerifyError(e)) => assert_eq!(e, "a new pristine-tar commit"),
+            other => panic!("unexpected {:?}", other),
+        }
+        commit(&workdir, "pristine-tar data");
+        self::git(&workdir, &["branch", "-f", "pristine-tar", "HEAD"]);
+        pkg.verify_import(git, "19.0.1~b1", pristine).unwrap();
+        fs::remove_dir_all(&rootdir).unwrap();
+    }
+
+    #[test]
+    fn normalize_upstream_version() {
+        let normalize = |v: &str| Package::normalize_upstream_version(v).unwrap();
+        assert_eq!(normalize("19.0.1"), "19.0.1");
+        assert_eq!(normalize("19.0.1~b1+git2019"), "19.0.1~b1+git2019");
+        assert_eq!(normalize("19.0.1_rc1"), "19.0.1.rc1");
+        assert_eq!(normalize("19.0.1-dev42"), "19.0.1.dev42");
+        for illegal in ["", "v19.0.1", "-19.0.1", "_1.0"] {
+            match Package::normalize_upstream_version(illegal) {
+                Err(Error::SnapshotVersionError(v)) => assert_eq!(v, illegal),
+                other => panic!("unexpected {:?} for {}", other, illegal),
+            }
+        }
+    }
+
+    #[test]
+    fn newer_upstream() {
+        let newer = |current, latest: &str| Package::newer_upstream(current, latest.to_string());
+        assert_eq!(
+            newer("19.0.0-0ubuntu1", "19.0.1").unwrap().as_deref(),
+            Some("19.0.1")
+        );
+        assert_eq!(newer("19.0.1-0ubuntu1", "19.0.1").unwrap(), None);
+        assert_eq!(newer("19.0.1~b1-0ubuntu1", "19.0.0").unwrap(), None);
+        assert_eq!(
+            newer("19.0.1~b1-0ubuntu1", "19.0.1").unwrap().as_deref(),
+            Some("19.0.1")
+        );
+    }
+
+    #[test]
+    fn status() {
+        let rootdir = temp_dir("status");
+        let mut pkg = checkout(&rootdir, "nova");
+        let workdir = rootdir.join("nova");
+        write(
+            &workdir,
+            "debian/changelog",
+            "nova (2:19.0.0-0ubuntu1) eoan; urgency=medium\n\n  \
+             * New upstream release for OpenStack Stein.\n\n \
+             -- Sahid Orentino Ferdjaoui <sahid.ferdjaoui@canonical.com>  \
+             Mon, 17 Jun 2019 15:30:00 +0000\n",
+        );
+        git(&workdir, &["add", "debian/changelog"]);
+        commit(&workdir, "debian/changelog");
+        let clean = pkg.status();
+        write(&workdir, "debian/patches/series", "");
+        let dirty = pkg.status();
+        pkg.git = None;
+        let not_cloned = pkg.status();
+        fs::remove_dir_all(&rootdir).unwrap();
+
+        let clean = clean.unwrap();
+        assert_eq!(clean.branch, "master");
+        assert_eq!(clean.version.as_deref(), Some("19.0.0-0ubuntu1"));
+        assert_eq!(clean.distribution.as_deref(), Some("eoan"));
+        assert!(clean.clean);
+        assert_eq!(
+            clean.to_string(),
+            format!(
+                "branch: master\nversion: 19.0.0-0ubuntu1\ndistribution: eoan\n\
+                 clean: yes\nnewer upstream: {}\n",
+                clean.newer_upstream.as_deref().unwrap_or("-")
+            )
+        );
+        assert!(!dirty.unwrap().clean);
+        assert!(matches!(not_cloned, Err(Error::Fatal(_))));
+    }
+
+    #[test]
+    fn private_temp_dir() {
+        use std::os::unix::fs::PermissionsExt;
+        let pkg = Package::new("nova", PathBuf::from("/nonexistent")).unwrap();
+        let first = pkg.private_temp_dir().unwrap();
+        let second = pkg.private_temp_dir().unwrap();
+        let mode = fs::metadata(&first).unwrap().permissions().mode();
+        fs::remove_dir(&first).unwrap();
+        fs::remove_dir(&second).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(mode & 0o777, 0o700);
+    }
 }
@@ -11,85 +11,64 @@
 
 #[macro_use]
 extern crate clap;
+#[macro_use]
+extern crate log;
 
 extern crate changelog;
+extern crate git;
 
-use changelog::ChangeLogMessage;
-use clap::{App, AppSettings, Arg, SubCommand};
+use changelog::ReleaseMode;
+use clap::{App, AppSettings, Arg, ArgMatches, Shell, SubCommand};
+use git::{Git, GitCloneUrl};
+use glob::Pattern;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use uosp::config::Config;
+use uosp::report::{Outcome, Report};
 use uosp::*;
 
-const OS_MASTER: &str = "ussuri";
-const KIND_OPENSTACK: &str = "openstack";
-// const KIND_REGULAR: &str = "regular";
+/// Set by the global --dry-run flag.
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
 
-fn get_current_dir() -> std::path::PathBuf {
-    std::env::current_dir().unwrap()
+fn dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
 }
 
-// https://stackoverflow.com/questions/38406793
-fn uppercase_first_letter(s: &str) -> String {
-    let mut c = s.chars();
-    match c.next() {
-        None => String::new(),
-        Some(f) => f.to_uppercase().collect::<String>() + c.as_str(),
-    }
+fn get_current_dir() -> std::path::PathBuf {
+    std::env::current_dir().unwrap()
 }
 
-/// Rebases a package to a new upstream version
-///
-/// The process is to create point release based on the last version
-/// published upstream. Running this command will clone project name
-/// using package VCS (see: --git-url <URL> if VCS not right). Using
-/// uscan to download tarball of the proposed version and import it
-/// using gbpimport-orig. Finally update the d/changelog and commit
-/// the all in git repo.
-fn rebase(
+fn debdiff(
     name: &str,
-    version: &str,
     release: &str,
-    bugid: Option<&str>,
-    kind: &str,
-    dist: &str,
+    patch: &str,
+    _upstream: Option<&str>,
+    strip: Option<u8>,
+    three_way: bool,
 ) -> Result<()> {
-    println!(
-        "Rebasing {} {} to new upstream version '{}'...",
-        name, release, version
-    );
-
     let workdir = get_current_dir();
     let branch = Package::format_branch(release);
 
-    let pkg = Package::clone(name, workdir.clone(), kind, dist)?;
+    let pkg = Package::clone(name, workdir.clone(), "openstack", "ubuntu", dry_run())?;
 
-    let git = pkg.git.as_ref().unwrap();
+    let git = pkg.git().unwrap();
     git.checkout("pristine-tar")?;
     git.checkout("upstream")?;
     git.checkout(&branch)?;
 
-    pkg.download_tarball(version)?;
-    // The actions in a package refer always to rootdir/name/
-    let archive = format!("../{}_{}.orig.tar.gz", name, version);
-    pkg.apply_tarball(version, &archive)?;
-
-    let chg = &pkg.changelog;
-    // TODO(sahid): Need to move all of that in changelog, the method
-    // whould be something like: chg.new_release(version, message, dist, kind)
-    let msg = if kind == KIND_OPENSTACK {
-        let formated_name = if release != "master" {
-            uppercase_first_letter(release)
-        } else {
-            uppercase_first_letter(OS_MASTER)
-        };
-        if let Some(bugid) = bugid {
-            ChangeLogMessage::OSNewStablePointReleaseWithBug(formated_name, bugid.to_string())
-        } else {
-            ChangeLogMessage::OSNewStablePointRelease(formated_name)
-        }
+    if patch.starts_with("http") {
+        // webpatch
+        git.apply_from_url(patch)?;
     } else {
-        // Assumes KIND_REGULAR
-        ChangeLogMessage::NewUpstreamRelease(version.to_string())
-    };
-    chg.new_release(version, msg);
+        // localpatch
+        let mut file = get_current_dir();
+        file.push(patch);
+        git.apply_from_file(file, strip, three_way)?;
+    }
 
     git.debcommit()?;
     git.show()?;
@@ -97,204 +76,630 @@ fn rebase(
     Ok(())
 }
 
-// TODO(sahid): should be merged with rebase, the diff si only in the
-// change log message.
-fn upstream(
-    name: &str,
-    version: &str,
-    release: &str,
-    bugid: Option<&str>,
-    kind: &str,
-    dist: &str,
-) -> Result<()> {
-    println!(
-        "Rebasing {} {} to new upstream version '{}'...",
-        name, release, version
-    );
-
-    let workdir = get_current_dir();
+/// Merges the upstream branch in the packaging branch of `release`.
+fn merge(name: &str, release: &str, strategy: Option<&str>) -> Result<()> {
     let branch = Package::format_branch(release);
+    info!("Merging upstream in {} {}...", name, branch);
 
-    let pkg = Package::clone(name, workdir.clone(), kind, dist)?;
-
-    let git = pkg.git.as_ref().unwrap();
-    git.checkout("pristine-tar")?;
+    let pkg = Package::clone(name, get_current_dir(), "openstack", "ubuntu", dry_run())?;
+    let git = pkg.git().unwrap();
     git.checkout("upstream")?;
     git.checkout(&branch)?;
-
-    pkg.download_tarball(version)?;
-    // The actions in a package refer always to rootdir/name/
-    let archive = format!("../{}_{}.orig.tar.gz", name, version);
-    pkg.apply_tarball(version, &archive)?;
-
-    let chg = &pkg.changelog;
-    // TODO(sahid): Need to move all of that in changelog, the method
-    // whould be something like: chg.new_release(version, message, dist, kind)
-    let msg = if kind == KIND_OPENSTACK {
-        let formated_name = if release != "master" {
-            uppercase_first_letter(release)
-        } else {
-            uppercase_first_letter(OS_MASTER)
-        };
-        ChangeLogMessage::OSNewUpstreamSnapshot(formated_name)
-    } else {
-        // Assumes KIND_REGULAR
-        ChangeLogMessage::NewUpstreamRelease(version.to_string())
-    };
-    chg.new_release(version, msg);
-
-    git.debcommit()?;
-    git.show()?;
+    git.merge("upstream", strategy)?;
 
     Ok(())
 }
 
-/// Creates snapshot of an upstream source and rebase the package with it.
-fn snapshot(name: &str, version: &str, upstream: Option<&str>) -> Result<()> {
-    println!("Updating package {} to a new upstream snapshot...", name);
-
-    let release = "master";
-    let workdir = get_current_dir();
+/// Applies a patch from `url` or a local `file`, relative to
+/// `rootdir`, on the packaging branch of `release` of the checkout in
+/// `rootdir`. The result is committed when `commit` is set.
+fn apply(
+    name: &str,
+    rootdir: &Path,
+    release: &str,
+    url: Option<&str>,
+    file: Option<&str>,
+    commit: bool,
+) -> Result<()> {
     let branch = Package::format_branch(release);
-    let pkg = Package::clone(name, workdir.clone(), "openstack", "ubuntu")?;
+    info!("Applying patch on {} {}...", name, branch);
 
-    let git = pkg.git.as_ref().unwrap();
-    git.checkout("pristine-tar")?;
-    git.checkout("upstream")?;
+    let pkg = Package::clone(
+        name,
+        rootdir.to_path_buf(),
+        "openstack",
+        "ubuntu",
+        dry_run(),
+    )?;
+    let git = pkg.git().unwrap();
     git.checkout(&branch)?;
+    match (url, file) {
+        (Some(url), _) => git.apply_from_url(url)?,
+        (None, Some(file)) => git.apply_from_file(rootdir.join(file), None, false)?,
+        (None, None) => return Err(Error::OptionError("missing --url or --file".to_string())),
+    }
+    if commit {
+        git.debcommit()?;
+        git.show()?;
+    }
 
-    let githash = pkg.generate_snapshot(release, version, upstream)?;
-    let gitversion = pkg.version_from_githash(version, &githash);
+    Ok(())
+}
 
-    // The actions in a package refer always to rootdir/name/
-    let nameup = match upstream {
-        Some(upstream) => upstream,
-        None => name,
-    };
-    let archive = format!("../{}_{}.orig.tar.gz", nameup, gitversion);
-    pkg.apply_tarball(version, &archive)?;
+/// How the packages are built by the build subcommand.
+struct BuildOptions {
+    build_area: bool,
+    /// The build fails when lintian reports errors.
+    lint: bool,
+    /// Builds the binary packages rather than the source package.
+    builder: Option<Builder>,
+}
 
-    let msg = ChangeLogMessage::OSNewUpstreamSnapshot(uppercase_first_letter(OS_MASTER));
-    let chg = &pkg.changelog;
-    chg.new_release(&gitversion, msg);
+/// Builds a package, its output is prefixed with its name when
+/// `prefix` is set.
+fn build(name: &str, opts: &BuildOptions, prefix: bool) -> Result<()> {
+    info!("Building {}...", name);
 
-    git.debcommit()?;
-    git.show()?;
+    let mut pkg = Package::new(name, get_current_dir())?;
+    // Builds an existing checkout, nothing is cloned.
+    if !pkg.exists() {
+        return Err(Error::NoCheckoutError(
+            name.to_string(),
+            pkg.workdir().display().to_string(),
+        ));
+    }
+    pkg.set_dry_run(dry_run());
+    pkg.set_build_area(opts.build_area);
+    if prefix {
+        pkg.set_output_prefix(Some(name.to_string()));
+    }
+    match &opts.builder {
+        Some(builder) => pkg.build_binary(builder.clone())?,
+        None => pkg.build()?,
+    }
+    if opts.lint {
+        let report = pkg.lint()?;
+        println!("[{}] {}", name, report);
+        if report.errors > 0 {
+            return Err(Error::LintViolationError(name.to_string(), report.errors));
+        }
+    }
+    Ok(())
+}
 
-    // Wanning that the process is not yet finished.
-    // TODO(sahid): implement some sort of magic to handle deps.
-    println!();
-    println!("/!\\ Please consider to check (build-)deps.");
+/// Builds the packages `names` using `build_one` with up to `jobs`
+/// builds running concurrently. All the packages are attempted, the
+/// error lists the ones which failed.
+fn build_all<F>(names: &[&str], jobs: usize, build_one: F) -> Result<()>
+where
+    F: Fn(&str) -> Result<()> + Sync,
+{
+    let queue = Mutex::new(names.iter());
+    let failed = Mutex::new(Vec::new());
+    thread::scope(|s| {
+        for _ in 0..jobs.min(names.len()) {
+            s.spawn(|| loop {
+                let name = match queue.lock().unwrap().next() {
+                    Some(name) => *name,
+                    None => break,
+                };
+                if let Err(e) = build_one(name) {
+                    error!("[{}] {}", name, e);
+                    failed.lock().unwrap().push(name.to_string());
+                }
+            });
+        }
+    });
+    let mut failed = failed.into_inner().unwrap();
+    if failed.is_empty() {
+        return Ok(());
+    }
+    // Keeps the order given on the command line.
+    failed.sort_by_key(|f| names.iter().position(|n| n == f));
+    Err(Error::BuildsError(failed))
+}
+
+/// Pull sources of debian packages.
+///
+/// By default the package is cloned from the OpenStack team services
+/// on salsa, `path` can be used for packages living elsewhere (e.g.
+/// openstack-team/libs/python-oslo.config).
+fn debpull(project: &str, path: Option<&str>) -> Result<()> {
+    info!("Pulling debian package '{}'...", project);
+
+    let path = match path {
+        Some(path) => path.to_string(),
+        None => format!("openstack-team/services/{}", project),
+    };
+    Git::new(
+        project,
+        get_current_dir(),
+        GitCloneUrl::Salsa(path),
+        dry_run(),
+    )?;
 
     Ok(())
 }
 
-fn debdiff(name: &str, release: &str, patch: &str, _upstream: Option<&str>) -> Result<()> {
-    let workdir = get_current_dir();
-    let branch = Package::format_branch(release);
+/// Returns the size in bytes of all the files under `path`.
+fn disk_usage(path: &Path) -> u64 {
+    match std::fs::symlink_metadata(path) {
+        Ok(m) if m.is_dir() => std::fs::read_dir(path)
+            .map(|entries| {
+                entries
+                    .filter_map(|e| e.ok())
+                    .map(|e| disk_usage(&e.path()))
+                    .sum()
+            })
+            .unwrap_or(0),
+        Ok(m) => m.len(),
+        Err(_) => 0,
+    }
+}
 
-    let pkg = Package::clone(name, workdir.clone(), "openstack", "ubuntu")?;
+/// Runs git gc on all the checkouts of the current directory.
+fn maintenance(aggressive: bool) -> Result<()> {
+    let mut reclaimed: i64 = 0;
+    for entry in std::fs::read_dir(get_current_dir())? {
+        let mut git = Git::open(entry?.path());
+        git.dry_run = dry_run();
+        // Only consider the roots of checkouts.
+        if !git.workdir.join(".git").exists() || !git.is_git_repo() {
+            continue;
+        }
+        // Linked worktrees share the repository of their main
+        // checkout, which is the one maintained.
+        if git.is_linked_worktree()? {
+            debug!("Skipping worktree '{}'.", git.workdir.display());
+            continue;
+        }
+        debug!("Maintaining '{}'...", git.workdir.display());
+        let before = disk_usage(&git.workdir);
+        git.prune_worktrees()?;
+        git.gc(aggressive)?;
+        reclaimed += before as i64 - disk_usage(&git.workdir) as i64;
+    }
+    info!("{} KiB reclaimed.", reclaimed / 1024);
+    Ok(())
+}
 
-    let git = pkg.git.as_ref().unwrap();
-    git.checkout("pristine-tar")?;
-    git.checkout("upstream")?;
-    git.checkout(&branch)?;
+/// Git push the current branch in a launchpad account, overwriting
+/// the remote one when `force` is set. With `all`, all the branches
+/// are force pushed.
+fn pushlp(name: &str, account: &str, all: bool, force: bool) -> Result<()> {
+    info!("Push package '{}' on lp:{}...", name, account);
 
-    if patch.starts_with("http") {
-        // webpatch
-        git.apply_from_url(patch)?;
+    let pkg = Package::clone(name, get_current_dir(), "openstack", "ubuntu", dry_run())?;
+    let url = GitCloneUrl::Launchpad {
+        owner: account.to_string(),
+        name: name.to_string(),
+    }
+    .ssh()
+    .to_string();
+    // The account is registered as a remote the first time.
+    let git = pkg.git().unwrap();
+    if !git.remotes()?.iter().any(|(remote, _)| remote == account) {
+        git.add_remote(account, &url)?;
+    }
+    if all {
+        git.force_all(account)?;
     } else {
-        // localpatch
-        let mut file = get_current_dir();
-        file.push(patch);
-        git.apply_from_file(file)?;
+        git.push_branch(account, &git.current_branch()?, force)?;
     }
 
-    git.debcommit()?;
-    git.show()?;
+    Ok(())
+}
 
+/// Prints an overview of the state of a package.
+fn status(name: &str) -> Result<()> {
+    let pkg = Package::clone(name, get_current_dir(), "openstack", "ubuntu", dry_run())?;
+    print!("{}", pkg.status()?);
     Ok(())
 }
 
-/// Builds a package.
-fn build(name: &str) -> Result<()> {
-    println!("Building {}...", name);
+/// Uploads the source changes built for a package to `target`.
+fn upload(name: &str, target: &str) -> Result<()> {
+    info!("Uploading package '{}' to {}...", name, target);
 
-    Package::new(name, get_current_dir())?.build()
+    let mut pkg = Package::new(name, get_current_dir())?;
+    pkg.set_dry_run(dry_run());
+    pkg.upload(target)
 }
 
-/// Clones package.
-fn clone(name: &str) -> Result<()> {
-    println!("Cloning package '{}'...", name);
+/// Removes the build-area contents and the generated files of a
+/// package.
+fn clean(name: &str) -> Result<()> {
+    info!("Cleaning package '{}'...", name);
 
-    let pkg = Package::clone(name, get_current_dir(), "openstack", "ubuntu")?;
+    let mut pkg = Package::new(name, get_current_dir())?;
+    pkg.set_dry_run(dry_run());
+    pkg.clean()
+}
 
-    let git = pkg.git.as_ref().unwrap();
-    git.checkout("pristine-tar")?;
-    git.checkout("upstream")?;
-    git.checkout("master")?;
+/// Pushes all the refs and tags of a package to the backup `target`,
+/// a remote or an URL.
+fn mirror(name: &str, target: &str) -> Result<()> {
+    info!("Mirror package '{}' to {}...", name, target);
+
+    let pkg = Package::clone(name, get_current_dir(), "openstack", "ubuntu", dry_run())?;
+    pkg.git().unwrap().mirror(target)?;
 
     Ok(())
 }
 
-fn publish(name: &str, ppa: &str, serie: &str, fake: bool, build: bool) -> Result<()> {
-    println!(
-        "Backport {} to '{}', ubuntu {}, fake-time: {:?}...",
-        name, ppa, serie, fake
-    );
+/// Configures the logger based on --verbose and --quiet, RUST_LOG
+/// can still be used to refine it.
+fn init_logger(matches: &ArgMatches) {
+    let level = if matches.is_present("quiet") {
+        log::LevelFilter::Warn
+    } else {
+        match matches.occurrences_of("verbose") {
+            0 => log::LevelFilter::Info,
+            1 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        }
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .target(env_logger::Target::Stdout)
+        .format(|buf, record| match record.level() {
+            log::Level::Info => writeln!(buf, "{}", record.args()),
+            l => writeln!(buf, "{}: {}", l.to_string().to_lowercase(), record.args()),
+        })
+        .init();
+}
+
+/// Returns the head version of an already cloned package.
+fn head_version(name: &str) -> Option<String> {
+    let pkg = Package::new(name, get_current_dir()).ok()?;
+    if !pkg.exists() {
+        return None;
+    }
+    pkg.changelog().get_head_version()
+}
 
-    let pkg = Package::clone(name, get_current_dir(), "openstack", "ubuntu")?;
-    if !build {
-        pkg.build()?;
+/// Returns the packages given to rebase, comma-separated. The
+/// options only making sense for one package are refused with
+/// several.
+fn rebase_names<'a>(matches: &'a ArgMatches) -> Result<Vec<&'a str>> {
+    let names: Vec<&str> = matches
+        .value_of("project")
+        .unwrap()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .collect();
+    if names.len() > 1 {
+        for (option, name) in &[
+            ("version", "VERSION"),
+            ("git-url", "--git-url"),
+            ("sha256", "--sha256"),
+        ] {
+            if matches.is_present(option) {
+                return Err(Error::OptionError(format!(
+                    "{} can only be used to rebase a single package",
+                    name
+                )));
+            }
+        }
     }
-    pkg.publish(ppa, serie, true)?;
+    Ok(names)
+}
 
-    Ok(())
+/// Rebases the package `name` according to the options of the rebase
+/// subcommand, returns the version rebased on.
+fn rebase_one(name: &str, matches: &ArgMatches, config: &Config) -> Result<String> {
+    let import = import_options(matches, matches.is_present("force-download"))?;
+    let mut opts = RebaseOptions::new(name)
+        .with_release(&config.release(matches.value_of("release")))
+        .with_kind(matches.value_of("kind").unwrap())
+        .with_dist(matches.value_of("dist").unwrap())
+        .with_import(import)
+        .with_plan(matches.is_present("plan"))
+        .with_upstream_log(matches.is_present("include-upstream-log"))
+        .with_diff_stat(matches.is_present("diff-stat"))
+        .with_tag(matches.is_present("tag"))
+        .with_changelog_only(matches.is_present("changelog-only"))
+        .with_mode(ReleaseMode::from(matches.value_of("mode").unwrap()))
+        .with_check_repo(matches.is_present("check-repo"))
+        .with_new_stanza(matches.is_present("new-stanza"))
+        .with_rootdir(get_current_dir())
+        .with_dry_run(dry_run());
+    opts.version = matches.value_of("version").map(str::to_string);
+    opts.bugid = matches.value_of("bugid").map(str::to_string);
+    opts.cve = matches.value_of("cve").map(|cve| {
+        let desc = matches.value_of("cve-desc").unwrap_or("security issue");
+        (cve.to_string(), desc.to_string())
+    });
+    opts.security = matches.value_of("security").map(str::to_string);
+    opts.git_url = matches
+        .value_of("git-url")
+        .map(str::to_string)
+        .or_else(|| config.git_url(name));
+    opts.maintainer = config.maintainer();
+    rebase(&opts)
 }
 
-/// Pull sources of debian packages.
-fn debpull(project: &str) -> Result<()> {
-    println!("Pulling debian package '{}'...", project);
+/// Prints the summary of `report`, and writes it to `--report-file`
+/// if requested.
+fn print_report(matches: &ArgMatches, report: &Report) -> Result<()> {
+    println!();
+    print!("{}", report);
+    if let Some(path) = matches.value_of("report-file") {
+        report.write(Path::new(path))?;
+    }
     Ok(())
 }
 
-/// Git push all the source in a launchpad account.
-fn pushlp(name: &str, account: &str) -> Result<()> {
-    println!("Push package '{}' on lp:{}...", name, account);
+/// Returns the glob patterns of `--exclude-branch`.
+fn exclude_patterns(matches: &ArgMatches) -> Result<Vec<Pattern>> {
+    matches
+        .values_of("exclude-branch")
+        .into_iter()
+        .flatten()
+        .map(|p| {
+            Pattern::new(p).map_err(|e| Error::OptionError(format!("invalid pattern {}, {}", p, e)))
+        })
+        .collect()
+}
 
-    let pkg = Package::clone(name, get_current_dir(), "openstack", "ubuntu")?;
-    let url = format!(
-        "git+ssh://{}@git.launchpad.net/~{}/ubuntu/+source/{}",
-        account, account, name
-    );
-    pkg.git.as_ref().unwrap().push(&url)?;
+/// Returns the series given to publish, as the positional serie or
+/// the repeated `--serie`, both accepting comma-separated values.
+fn publish_series(matches: &ArgMatches, config: &Config) -> Result<Vec<String>> {
+    let series: Vec<String> = matches
+        .values_of("serie")
+        .into_iter()
+        .flatten()
+        .chain(matches.values_of("series").into_iter().flatten())
+        .flat_map(|s| s.split(','))
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect();
+    if series.is_empty() {
+        return Ok(vec![config.serie(None)?]);
+    }
+    Ok(series)
+}
 
-    Ok(())
+/// Prints the result of the backport to each serie, fails when one
+/// of them failed.
+fn print_publish_summary(
+    opts: &PublishOptions,
+    results: &[(String, Result<Published>)],
+) -> Result<()> {
+    println!();
+    for (serie, result) in results {
+        match result {
+            Ok(_) => println!("{}: ok", serie),
+            Err(e) => println!("{}: failed, {}", serie, e),
+        }
+    }
+    let failed: Vec<&str> = results
+        .iter()
+        .filter(|(_, result)| result.is_err())
+        .map(|(serie, _)| serie.as_str())
+        .collect();
+    if failed.is_empty() {
+        return Ok(());
+    }
+    Err(Error::PublishError(format!(
+        "{} for {}",
+        opts.dput_host.as_deref().unwrap_or(&opts.ppa),
+        failed.join(", ")
+    )))
+}
+
+/// Builds the import options from the `--merge-mode`, `--no-merge`,
+/// `--verify` and `--sha256` arguments.
+fn import_options(matches: &ArgMatches, force_download: bool) -> Result<ImportOptions> {
+    let sha256 = matches.value_of("sha256");
+    if let Some(sha256) = sha256 {
+        if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Error::OptionError(format!(
+                "invalid sha256 {}, 64 hexadecimal digits expected",
+                sha256
+            )));
+        }
+    }
+    Ok(ImportOptions {
+        force_download,
+        verify_signature: matches.is_present("verify"),
+        sha256: sha256.map(str::to_string),
+        merge: ImportMerge::new(
+            !matches.is_present("no-merge"),
+            matches.value_of("merge-mode"),
+        )?,
+        compression: TarballCompression::from(
+            matches.value_of("tarball-compression").unwrap_or("none"),
+        ),
+    })
 }
+
 // The below deprecation is allowed because of a deprecation in
 // the Clap library: https://github.com/clap-rs/clap/issues/1552
 #[allow(deprecated)]
-fn cli() -> std::result::Result<(), ()> {
-    let matches = App::new(crate_name!())
+fn build_cli() -> App<'static, 'static> {
+    App::new(crate_name!())
         .version(crate_version!())
-        .author(crate_authors!("\n"))
+        .author(env!("CARGO_PKG_AUTHORS"))
         .setting(AppSettings::GlobalVersion)
         .setting(AppSettings::SubcommandRequired)
         .setting(AppSettings::ColoredHelp)
+        .after_help(
+            "EXIT STATUS:
+    0  success
+    1  any other failure
+    2  version or tarball error (download, import, verification)
+    3  git error (clone, checkout, merge conflict, dirty tree...)
+    4  build error
+    5  publish or upload error",
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .short("n")
+                .long("dry-run")
+                .global(true)
+                .help("Prints the commands instead of running them."),
+        )
+        .arg(
+            Arg::with_name("isolated")
+                .long("isolated")
+                .global(true)
+                .help(
+                    "Runs git ignoring the system and global configurations of the user, \
+                     for reproducible runs.",
+                ),
+        )
+        .arg(
+            Arg::with_name("yes")
+                .short("y")
+                .long("yes")
+                .global(true)
+                .help(
+                    "Never prompts, the wrapped commands are run non-interactively \
+                     with their standard input closed.",
+                ),
+        )
+        .arg(
+            Arg::with_name("prefix-output")
+                .long("prefix-output")
+                .global(true)
+                .help(
+                    "Prefixes each line output by the wrapped commands with the name \
+                     of the package, e.g. [nova].",
+                ),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("PATH")
+                .global(true)
+                .takes_value(true)
+                .help(
+                    "Reads the defaults from PATH rather than $XDG_CONFIG_HOME/uosp/config.toml.",
+                ),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .value_name("SECS")
+                .global(true)
+                .default_value("600")
+                .help(
+                    "Kills the wrapped commands running longer than SECS, builds (gbp \
+                     buildpackage, sbuild) excepted. 0 disables it.",
+                ),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .global(true)
+                .multiple(true)
+                .help("Increases verbosity, -vv logs every command executed."),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .global(true)
+                .conflicts_with("verbose")
+                .help("Only logs warnings and errors."),
+        )
         .subcommand(
             SubCommand::with_name("rebase")
                 .about("Rebase package to a new upstream release.")
+                .arg(
+                    Arg::with_name("new-stanza")
+                        .long("new-stanza")
+                        .help(
+                            "Always create a new changelog entry, otherwise an UNRELEASED \
+                             top entry is continued.",
+                        )
+                        .required(false),
+                )
                 .arg(
                     Arg::with_name("project")
                         .value_name("PACKAGE")
-                        .help("The package name. (e.g. nova).")
+                        .help(
+                            "The package name, or comma-separated names rebased in a row \
+                             with --latest. (e.g. nova).",
+                        )
                         .required(true),
                 )
                 .arg(
                     Arg::with_name("version")
                         .value_name("VERSION")
                         .help("Openstack version to rebase on. (e.g. 19.0.1).")
-                        .required(true),
+                        .required_unless("latest"),
+                )
+                .arg(
+                    Arg::with_name("mode")
+                        .long("mode")
+                        .takes_value(true)
+                        .default_value("new-upstream")
+                        .possible_values(&["new-upstream", "merge", "rebuild"])
+                        .help(
+                            "Indicate how the package revision is determined, reset for a \
+                             new upstream (e.g. -0ubuntu1) or continued for a merge or a \
+                             rebuild (e.g. -0ubuntu3 -> -0ubuntu4).",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("git-url")
+                        .long("git-url")
+                        .value_name("URL")
+                        .takes_value(true)
+                        .help(
+                            "Clone the package from URL rather than its VCS, also accepts \
+                             <forge>:<path> (e.g. salsa:openstack-team/nova).",
+                        )
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("check-repo")
+                        .long("check-repo")
+                        .help("Verify the checkout is not corrupted before rebasing.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("changelog-only")
+                        .long("changelog-only")
+                        .help(
+                            "Only add a new release to d/changelog and commit it, in an \
+                             existing checkout (e.g. for a rebuild).",
+                        )
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("tag")
+                        .long("tag")
+                        .help("Tag the release commit as debian/<version>.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("diff-stat")
+                        .long("diff-stat")
+                        .help("Show a diff stat of the upstream import.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("include-upstream-log")
+                        .long("include-upstream-log")
+                        .help(
+                            "Append to the commit message the upstream commits since \
+                             the previous version.",
+                        )
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("latest")
+                        .long("latest")
+                        .conflicts_with("version")
+                        .help("Rebase on the latest upstream version found by uscan.")
+                        .required(false),
                 )
                 .arg(
                     Arg::with_name("release")
@@ -306,7 +711,6 @@ fn cli() -> std::result::Result<(), ()> {
                              Default will be to consider to use the in-progress \
                              release 'master'.",
                         )
-                        .default_value("master")
                         .required(false),
                 )
                 .arg(
@@ -317,16 +721,40 @@ fn cli() -> std::result::Result<(), ()> {
                         .help("Launchpad bug ID associated to the rebase (e.g: 123456).")
                         .required(false),
                 )
+                .arg(
+                    Arg::with_name("cve")
+                        .long("cve")
+                        .takes_value(true)
+                        .help("CVE fixed by the rebase (e.g: 2019-12345).")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("cve-desc")
+                        .long("cve-desc")
+                        .takes_value(true)
+                        .requires("cve")
+                        .help("Description of the CVE fixed by the rebase.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("security")
+                        .long("security")
+                        .takes_value(true)
+                        .value_name("DESC")
+                        .help("Security issue fixed by the rebase, when no CVE is assigned.")
+                        .required(false),
+                )
                 .arg(
                     Arg::with_name("kind")
                         .short("k")
                         .long("kind")
                         .takes_value(true)
                         .default_value("openstack")
-                        .possible_values(&["openstack", "regular"])
+                        .possible_values(&["openstack", "regular", "auto"])
                         .help(
                             "Indicate what kind of package it is, this help determining \
-                             version and change log message.",
+                             version and change log message. Using 'auto' detects it \
+                             from debian/control.",
                         )
                         .required(false),
                 )
@@ -342,11 +770,84 @@ fn cli() -> std::result::Result<(), ()> {
                              version and change log message.",
                         )
                         .required(false),
+                )
+                .arg(
+                    Arg::with_name("force-download")
+                        .long("force-download")
+                        .help("Download the tarball even if it already exists.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("verify")
+                        .long("verify")
+                        .help(
+                            "Verify the downloaded tarball against its upstream signature \
+                             (.asc) with the key of debian/upstream/signing-key.asc, fails \
+                             when missing or not matching.",
+                        )
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("sha256")
+                        .long("sha256")
+                        .takes_value(true)
+                        .value_name("HEX")
+                        .help("Verify the downloaded tarball has the sha256 checksum HEX.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("plan")
+                        .long("plan")
+                        .help("Print the actions of the rebase without executing them.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("report")
+                        .long("report")
+                        .help("Print a summary of the run.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("report-file")
+                        .long("report-file")
+                        .value_name("PATH")
+                        .takes_value(true)
+                        .help("Write the summary of the run to PATH, as JSON for .json else CSV.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("merge-mode")
+                        .long("merge-mode")
+                        .takes_value(true)
+                        .possible_values(&["auto", "merge", "replace"])
+                        .help(
+                            "Merge mode used by gbp import-orig to integrate upstream \
+                             in the packaging branch. Default is 'replace'.",
+                        )
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("no-merge")
+                        .long("no-merge")
+                        .help(
+                            "Only import upstream, do not merge it in the packaging branch. \
+                             Can't be used with --merge-mode.",
+                        )
+                        .required(false),
                 ),
         )
         .subcommand(
             SubCommand::with_name("upstream")
                 .about("New upstream release.")
+                .arg(
+                    Arg::with_name("new-stanza")
+                        .long("new-stanza")
+                        .help(
+                            "Always create a new changelog entry, otherwise an UNRELEASED \
+                             top entry is continued.",
+                        )
+                        .required(false),
+                )
                 .arg(
                     Arg::with_name("project")
                         .value_name("PACKAGE")
@@ -369,7 +870,6 @@ fn cli() -> std::result::Result<(), ()> {
                              Default will be to consider to use the in-progress \
                              release 'master'.",
                         )
-                        .default_value("master")
                         .required(false),
                 )
                 .arg(
@@ -386,10 +886,11 @@ fn cli() -> std::result::Result<(), ()> {
                         .long("kind")
                         .takes_value(true)
                         .default_value("openstack")
-                        .possible_values(&["openstack", "regular"])
+                        .possible_values(&["openstack", "regular", "auto"])
                         .help(
                             "Indicate what kind of package it is, this help determining \
-                             version and change log message.",
+                             version and change log message. Using 'auto' detects it \
+                             from debian/control.",
                         )
                         .required(false),
                 )
@@ -405,11 +906,46 @@ fn cli() -> std::result::Result<(), ()> {
                              version and change log message.",
                         )
                         .required(false),
+                )
+                .arg(
+                    Arg::with_name("force-download")
+                        .long("force-download")
+                        .help("Download the tarball even if it already exists.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("merge-mode")
+                        .long("merge-mode")
+                        .takes_value(true)
+                        .possible_values(&["auto", "merge", "replace"])
+                        .help(
+                            "Merge mode used by gbp import-orig to integrate upstream \
+                             in the packaging branch. Default is 'replace'.",
+                        )
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("no-merge")
+                        .long("no-merge")
+                        .help(
+                            "Only import upstream, do not merge it in the packaging branch. \
+                             Can't be used with --merge-mode.",
+                        )
+                        .required(false),
                 ),
         )
         .subcommand(
             SubCommand::with_name("snapshot")
                 .about("Update an Ubuntu package to a new upstream snapshot.")
+                .arg(
+                    Arg::with_name("new-stanza")
+                        .long("new-stanza")
+                        .help(
+                            "Always create a new changelog entry, otherwise an UNRELEASED \
+                             top entry is continued.",
+                        )
+                        .required(false),
+                )
                 .arg(
                     Arg::with_name("project")
                         //.short("p").long("project").takes_value(true)
@@ -429,6 +965,119 @@ fn cli() -> std::result::Result<(), ()> {
                         .takes_value(true)
                         .help("Upstream name used to grab source on github. (e.g. trove).")
                         .required(false),
+                )
+                .arg(
+                    Arg::with_name("rev")
+                        .long("rev")
+                        .takes_value(true)
+                        .value_name("REV")
+                        .help(
+                            "Upstream commit or tag to snapshot instead of the HEAD of the \
+                             branch, for reproducible snapshots.",
+                        )
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("merge-mode")
+                        .long("merge-mode")
+                        .takes_value(true)
+                        .possible_values(&["auto", "merge", "replace"])
+                        .help(
+                            "Merge mode used by gbp import-orig to integrate upstream \
+                             in the packaging branch. Default is 'replace'.",
+                        )
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("no-merge")
+                        .long("no-merge")
+                        .help(
+                            "Only import upstream, do not merge it in the packaging branch. \
+                             Can't be used with --merge-mode.",
+                        )
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("tarball-compression")
+                        .long("tarball-compression")
+                        .takes_value(true)
+                        .possible_values(&["none", "xz"])
+                        .default_value("none")
+                        .help("Recompress the generated tarball before to import it.")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("merge")
+                .about("Merge the upstream branch in the packaging branch.")
+                .arg(
+                    Arg::with_name("project")
+                        .help("Openstack package name. (e.g. nova).")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("release")
+                        .short("r")
+                        .long("release")
+                        .takes_value(true)
+                        .help(
+                            "Openstack release name. (e.g. stein). \
+                             Default will be to consider to use the in-progress \
+                             release 'master'.",
+                        )
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("strategy")
+                        .short("s")
+                        .long("strategy")
+                        .takes_value(true)
+                        .help("Merge strategy passed to git merge. (e.g. ours).")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("apply")
+                .about("Apply a patch to the working tree of a package.")
+                .arg(
+                    Arg::with_name("project")
+                        .help("Openstack package name. (e.g. nova).")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("release")
+                        .short("r")
+                        .long("release")
+                        .takes_value(true)
+                        .help(
+                            "Openstack release name. (e.g. stein). \
+                             Default will be to consider to use the in-progress \
+                             release 'master'.",
+                        )
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("url")
+                        .long("url")
+                        .value_name("URL")
+                        .takes_value(true)
+                        .conflicts_with("file")
+                        .required_unless("file")
+                        .help("Web patch to apply. (e.g. a launchpad merge proposal diff)."),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .long("file")
+                        .value_name("PATH")
+                        .takes_value(true)
+                        .required_unless("url")
+                        .help("Local patch to apply."),
+                )
+                .arg(
+                    Arg::with_name("commit")
+                        .long("commit")
+                        .help("Commit the result using debcommit.")
+                        .required(false),
                 ),
         )
         .subcommand(
@@ -449,7 +1098,6 @@ fn cli() -> std::result::Result<(), ()> {
                              Default will be to consider to use the in-progress \
                              release 'master'.",
                         )
-                        .default_value("master")
                         .required(false),
                 )
                 .arg(
@@ -464,15 +1112,84 @@ fn cli() -> std::result::Result<(), ()> {
                         .takes_value(true)
                         .help("Upstream name used to grab source on github. (e.g. trove).")
                         .required(false),
+                )
+                .arg(
+                    Arg::with_name("strip")
+                        .short("p")
+                        .long("strip")
+                        .value_name("NUM")
+                        .takes_value(true)
+                        .help("Remove NUM leading components of the local patch paths.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("3way")
+                        .long("3way")
+                        .help("Attempt a 3-way merge if the local patch does not apply.")
+                        .required(false),
                 ),
         )
         .subcommand(
             SubCommand::with_name("build")
-                .about("Build the Ubuntu package.")
+                .about("Build the Ubuntu packages.")
                 .arg(
                     Arg::with_name("project")
-                        .help("Openstack package name. (e.g. nova).")
+                        .help("Openstack package names. (e.g. nova neutron).")
+                        .multiple(true)
                         .required(true),
+                )
+                .arg(
+                    Arg::with_name("binary")
+                        .long("binary")
+                        .conflicts_with("lint")
+                        .help(
+                            "Build the binary packages in a clean chroot, using sbuild \
+                             with --chroot or gbp --git-pbuilder otherwise.",
+                        )
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("chroot")
+                        .long("chroot")
+                        .takes_value(true)
+                        .value_name("NAME")
+                        .requires("binary")
+                        .help("sbuild chroot used by --binary (e.g. eoan-amd64).")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("lint")
+                        .long("lint")
+                        .help("Run lintian on the build, fails when it reports errors.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("parallel")
+                        .long("parallel")
+                        .help(
+                            "Build the packages concurrently, the output is \
+                             prefixed with the package name.",
+                        )
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("jobs")
+                        .short("j")
+                        .long("jobs")
+                        .takes_value(true)
+                        .value_name("N")
+                        .requires("parallel")
+                        .help("Maximum number of concurrent builds, defaults to all.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("no-build-area")
+                        .long("no-build-area")
+                        .help(
+                            "Do not create the build-area directory, for workflows \
+                             using their own export-dir.",
+                        )
+                        .required(false),
                 ),
         )
         .subcommand(
@@ -486,15 +1203,57 @@ fn cli() -> std::result::Result<(), ()> {
                 )
                 .arg(
                     Arg::with_name("ppa")
-                        //.short("P").long("ppa").takes_value(true)
-                        .help("Launchpad PPA used. (e.g. ppa:sahid-ferdjaoui/eoan-train).")
-                        .required(true),
+                        .short("P")
+                        .long("ppa")
+                        .value_name("PPA")
+                        .takes_value(true)
+                        .help(
+                            "Launchpad PPA used. (e.g. ppa:sahid-ferdjaoui/eoan-train). \
+                             Defaults to default_ppa of the configuration.",
+                        )
+                        .required(false),
                 )
                 .arg(
                     Arg::with_name("serie")
                         //.short("s").long("serie").takes_value(true)
-                        .help("Ubuntu serie used to build package. (e.g. eoan)")
-                        .required(true),
+                        .help(
+                            "Ubuntu series used to build package, comma-separated. (e.g. \
+                             eoan,disco) Defaults to default_serie of the configuration.",
+                        )
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("series")
+                        .short("s")
+                        .long("serie")
+                        .value_name("SERIE")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help(
+                            "Also backport to SERIE, can be repeated or comma-separated. \
+                             A failing serie does not stop the next ones.",
+                        )
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("dput-host")
+                        .long("dput-host")
+                        .value_name("HOST")
+                        .takes_value(true)
+                        .help("Upload through HOST of dput.cf rather than the PPA.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("fake-time")
+                        .long("fake-time")
+                        .value_name("TIME")
+                        .takes_value(true)
+                        .help(
+                            "Run backportpackage under faketime at TIME (e.g. \
+                             '2019-06-17 15:00:00' UTC) for reproducible versions.",
+                        )
+                        .required(false),
                 )
                 .arg(
                     Arg::with_name("build")
@@ -502,12 +1261,31 @@ fn cli() -> std::result::Result<(), ()> {
                         .long("build")
                         .help("Execute package build before publishing.")
                         .required(false),
+                )
+                .arg(
+                    Arg::with_name("no-build-area")
+                        .long("no-build-area")
+                        .help(
+                            "Do not create the build-area directory, for workflows \
+                             using their own export-dir.",
+                        )
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("upload")
+                .about("Upload the source package built to an archive with dput.")
+                .arg(
+                    Arg::with_name("project")
+                        .help("Openstack package name. (e.g. nova).")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("target")
+                        .help("dput host to upload to. (e.g. ppa:sahid-ferdjaoui/train).")
+                        .required(true),
                 ),
         )
-        /*
-        .arg(Arg::with_name("fake")
-             .help("Use fake timestamp.")
-             .required(true)))*/
         .subcommand(
             SubCommand::with_name("clone")
                 .about("Git clone OpenStack package from Ubuntu repository.")
@@ -516,11 +1294,75 @@ fn cli() -> std::result::Result<(), ()> {
                         //.short("p").long("project").takes_value(true)
                         .help("Openstack package name. (e.g. nova).")
                         .required(true),
+                )
+                .arg(
+                    Arg::with_name("exclude-branch")
+                        .long("exclude-branch")
+                        .value_name("PATTERN")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Do not checkout branches matching the glob PATTERN.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("continue-on-missing-branch")
+                        .long("continue-on-missing-branch")
+                        .help("Skip pristine-tar and upstream branches when missing.")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("debpull")
+                .about("Git clone Debian package from salsa.")
+                .arg(
+                    Arg::with_name("project")
+                        .help("Debian package name. (e.g. nova).")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("salsa-path")
+                        .short("p")
+                        .long("salsa-path")
+                        .takes_value(true)
+                        .help(
+                            "Path of the repository on salsa. \
+                             (default: openstack-team/services/<PACKAGE>).",
+                        )
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("status")
+                .about("Summarize the state of a package checkout.")
+                .arg(
+                    Arg::with_name("project")
+                        .help("Openstack package name. (e.g. nova).")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("clean")
+                .about("Remove the build artifacts of a package, its checkout is kept.")
+                .arg(
+                    Arg::with_name("project")
+                        .help("Openstack package name. (e.g. nova).")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("maintenance")
+                .about("Garbage collect all the package checkouts.")
+                .arg(
+                    Arg::with_name("aggressive")
+                        .long("aggressive")
+                        .help("Optimize the repositories more aggressively, this takes time.")
+                        .required(false),
                 ),
         )
         .subcommand(
             SubCommand::with_name("pushlp")
-                .about("Force push branch on a git launchpad account.")
+                .about("Push branch on a git launchpad account.")
                 .arg(
                     Arg::with_name("project")
                         //.short("p").long("project").takes_value(true)
@@ -532,77 +1374,479 @@ fn cli() -> std::result::Result<(), ()> {
                         //.short("a").long("account").takes_value(true)
                         .help("Launchpad account. (e.g. sahid-ferdjaoui).")
                         .required(true),
+                )
+                .arg(
+                    Arg::with_name("all")
+                        .long("all")
+                        .help("Force push all the branches, overwriting the remote ones.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .short("f")
+                        .long("force")
+                        .help("Force push the branch, overwriting the remote one.")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("mirror")
+                .about("Push all the refs and tags of a package to a backup remote.")
+                .arg(
+                    Arg::with_name("project")
+                        .help("Openstack package name. (e.g. nova).")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("target")
+                        .help("Backup remote name or URL, origin is refused.")
+                        .required(true),
                 ),
         )
-        .get_matches();
+        .subcommand(
+            SubCommand::with_name("completions")
+                .about("Print the completion script of a shell.")
+                .arg(
+                    Arg::with_name("shell")
+                        .possible_values(&Shell::variants())
+                        .help("Shell to generate the completion script for.")
+                        .required(true),
+                ),
+        )
+}
+
+fn cli() -> std::result::Result<(), i32> {
+    let matches = build_cli().get_matches();
+
+    // Handled first, nothing else has to be printed.
+    if let Some(matches) = matches.subcommand_matches("completions") {
+        let shell = matches.value_of("shell").unwrap().parse::<Shell>().unwrap();
+        build_cli().gen_completions_to(crate_name!(), shell, &mut std::io::stdout());
+        return Ok(());
+    }
+
+    DRY_RUN.store(matches.is_present("dry-run"), Ordering::Relaxed);
+    git::set_isolated(matches.is_present("isolated"));
+    process::set_non_interactive(matches.is_present("yes"));
+    process::set_prefix_output(matches.is_present("prefix-output"));
+    changelog::set_non_interactive(matches.is_present("yes"));
+    init_logger(&matches);
+
+    match matches.value_of("timeout").unwrap().parse::<u64>() {
+        Ok(0) => process::set_timeout(None),
+        Ok(secs) => process::set_timeout(Some(Duration::from_secs(secs))),
+        Err(_) => {
+            let e = Error::OptionError(format!(
+                "invalid timeout {}",
+                matches.value_of("timeout").unwrap()
+            ));
+            error!("app error, {}", e);
+            return Err(e.exit_code());
+        }
+    }
+
+    let config = match Config::load(matches.value_of("config").map(Path::new)) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("app error, {}", e);
+            return Err(e.exit_code());
+        }
+    };
+    if config.maintainer().is_none()
+        && (config.maintainer_name.is_some() || config.maintainer_email.is_some())
+    {
+        warn!("maintainer_name and maintainer_email have to be set together, ignored.");
+    }
 
     let mut ret: Result<()> = Err(Error::Fatal(
         "please consider using one of the subcommands, --help can help :)".to_string(),
     ));
 
     if let Some(matches) = matches.subcommand_matches("rebase") {
-        ret = rebase(
-            matches.value_of("project").unwrap(),
-            matches.value_of("version").unwrap(),
-            matches.value_of("release").unwrap(),
-            matches.value_of("bugid"),
-            matches.value_of("kind").unwrap(),
-            matches.value_of("dist").unwrap(),
-        );
+        ret = rebase_names(matches).and_then(|names| {
+            let mut report = Report::new();
+            let mut failed = Vec::new();
+            let mut last = Ok(());
+            for name in &names {
+                let old_version = head_version(name);
+                let start = Instant::now();
+                let rebased = rebase_one(name, matches, &config);
+                let new_version = match &rebased {
+                    Ok(version) => version.clone(),
+                    Err(e) => {
+                        error!("[{}] {}", name, e);
+                        failed.push(name.to_string());
+                        matches.value_of("version").unwrap_or("-").to_string()
+                    }
+                };
+                report.push(Outcome {
+                    name: name.to_string(),
+                    old_version,
+                    new_version,
+                    success: rebased.is_ok(),
+                    elapsed: start.elapsed(),
+                });
+                last = rebased.map(|_| ());
+            }
+            // A single package keeps its own error, and exit code.
+            let rebased = match failed.len() {
+                0 => Ok(()),
+                _ if names.len() == 1 => last,
+                _ => Err(Error::RebasesError(failed)),
+            };
+            if matches.is_present("report") || matches.is_present("report-file") {
+                rebased.and(print_report(matches, &report))
+            } else {
+                rebased
+            }
+        });
     } else if let Some(matches) = matches.subcommand_matches("upstream") {
-        ret = upstream(
-            matches.value_of("project").unwrap(),
-            matches.value_of("version").unwrap(),
-            matches.value_of("release").unwrap(),
-            matches.value_of("bugid"),
-            matches.value_of("kind").unwrap(),
-            matches.value_of("dist").unwrap(),
-        );
+        ret = import_options(matches, matches.is_present("force-download")).and_then(|import| {
+            let mut opts = UpstreamOptions::new(
+                matches.value_of("project").unwrap(),
+                matches.value_of("version").unwrap(),
+            )
+            .with_release(&config.release(matches.value_of("release")))
+            .with_kind(matches.value_of("kind").unwrap())
+            .with_dist(matches.value_of("dist").unwrap())
+            .with_import(import)
+            .with_new_stanza(matches.is_present("new-stanza"))
+            .with_rootdir(get_current_dir())
+            .with_dry_run(dry_run());
+            opts.bugid = matches.value_of("bugid").map(str::to_string);
+            opts.maintainer = config.maintainer();
+            upstream(&opts)
+        });
     } else if let Some(matches) = matches.subcommand_matches("build") {
-        ret = build(matches.value_of("project").unwrap());
+        let names: Vec<&str> = matches.values_of("project").unwrap().collect();
+        let opts = BuildOptions {
+            build_area: !matches.is_present("no-build-area"),
+            lint: matches.is_present("lint"),
+            builder: match (matches.is_present("binary"), matches.value_of("chroot")) {
+                (false, _) => None,
+                (true, Some(chroot)) => Some(Builder::Sbuild(chroot.to_string())),
+                (true, None) => Some(Builder::Pbuilder),
+            },
+        };
+        ret = if matches.is_present("parallel") {
+            match matches.value_of("jobs").map(str::parse::<usize>) {
+                Some(Ok(0)) | Some(Err(_)) => Err(Error::OptionError(
+                    "--jobs expects a positive number".to_string(),
+                )),
+                Some(Ok(jobs)) => build_all(&names, jobs, |name| build(name, &opts, true)),
+                None => build_all(&names, names.len(), |name| build(name, &opts, true)),
+            }
+        } else {
+            build_all(&names, 1, |name| build(name, &opts, false))
+        };
     } else if let Some(matches) = matches.subcommand_matches("snapshot") {
-        ret = snapshot(
+        ret = import_options(matches, false).and_then(|import| {
+            let mut opts = SnapshotOptions::new(
+                matches.value_of("project").unwrap(),
+                matches.value_of("version").unwrap(),
+            )
+            .with_import(import)
+            .with_new_stanza(matches.is_present("new-stanza"))
+            .with_rootdir(get_current_dir())
+            .with_dry_run(dry_run());
+            opts.upstream = matches.value_of("upstream").map(str::to_string);
+            opts.rev = matches.value_of("rev").map(str::to_string);
+            opts.maintainer = config.maintainer();
+            snapshot(&opts)
+        });
+    } else if let Some(matches) = matches.subcommand_matches("merge") {
+        ret = merge(
             matches.value_of("project").unwrap(),
-            matches.value_of("version").unwrap(),
-            matches.value_of("upstream"),
+            &config.release(matches.value_of("release")),
+            matches.value_of("strategy"),
         );
-    } else if let Some(matches) = matches.subcommand_matches("debdiff") {
-        ret = debdiff(
+    } else if let Some(matches) = matches.subcommand_matches("apply") {
+        ret = apply(
             matches.value_of("project").unwrap(),
-            matches.value_of("release").unwrap(),
-            matches.value_of("patch").unwrap(),
-            matches.value_of("upstream"),
+            &get_current_dir(),
+            &config.release(matches.value_of("release")),
+            matches.value_of("url"),
+            matches.value_of("file"),
+            matches.is_present("commit"),
         );
+    } else if let Some(matches) = matches.subcommand_matches("debdiff") {
+        ret = matches
+            .value_of("strip")
+            .map(|strip| {
+                strip
+                    .parse::<u8>()
+                    .map_err(|_| Error::OptionError(format!("invalid strip level {}", strip)))
+            })
+            .transpose()
+            .and_then(|strip| {
+                debdiff(
+                    matches.value_of("project").unwrap(),
+                    &config.release(matches.value_of("release")),
+                    matches.value_of("patch").unwrap(),
+                    matches.value_of("upstream"),
+                    strip,
+                    matches.is_present("3way"),
+                )
+            });
     } else if let Some(matches) = matches.subcommand_matches("publish") {
-        ret = publish(
+        ret = config.ppa(matches.value_of("ppa")).and_then(|ppa| {
+            let opts = PublishOptions {
+                name: matches.value_of("project").unwrap().to_string(),
+                ppa,
+                series: publish_series(matches, &config)?,
+                dput_host: matches.value_of("dput-host").map(str::to_string),
+                fake_time: matches.value_of("fake-time").map(str::to_string),
+                build: matches.is_present("build"),
+                build_area: !matches.is_present("no-build-area"),
+                rootdir: get_current_dir(),
+                dry_run: dry_run(),
+            };
+            print_publish_summary(&opts, &publish(&opts)?)
+        });
+    } else if let Some(matches) = matches.subcommand_matches("upload") {
+        ret = upload(
             matches.value_of("project").unwrap(),
-            matches.value_of("ppa").unwrap(),
-            matches.value_of("serie").unwrap(),
-            /*matches.value_of("fake").unwrap()*/ true,
-            matches.is_present("build"),
+            matches.value_of("target").unwrap(),
         );
     } else if let Some(matches) = matches.subcommand_matches("clone") {
-        ret = clone(matches.value_of("project").unwrap());
+        ret = exclude_patterns(matches).and_then(|exclude| {
+            clone(&CloneOptions {
+                name: matches.value_of("project").unwrap().to_string(),
+                exclude,
+                continue_on_missing: matches.is_present("continue-on-missing-branch"),
+                rootdir: get_current_dir(),
+                dry_run: dry_run(),
+            })
+        });
     } else if let Some(matches) = matches.subcommand_matches("debpull") {
-        ret = debpull(matches.value_of("project").unwrap());
+        ret = debpull(
+            matches.value_of("project").unwrap(),
+            matches.value_of("salsa-path"),
+        );
+    } else if let Some(matches) = matches.subcommand_matches("status") {
+        ret = status(matches.value_of("project").unwrap());
+    } else if let Some(matches) = matches.subcommand_matches("clean") {
+        ret = clean(matches.value_of("project").unwrap());
+    } else if let Some(matches) = matches.subcommand_matches("maintenance") {
+        ret = maintenance(matches.is_present("aggressive"));
     } else if let Some(matches) = matches.subcommand_matches("pushlp") {
         ret = pushlp(
             matches.value_of("project").unwrap(),
             matches.value_of("account").unwrap(),
+            matches.is_present("all"),
+            matches.is_present("force"),
+        );
+    } else if let Some(matches) = matches.subcommand_matches("mirror") {
+        ret = mirror(
+            matches.value_of("project").unwrap(),
+            matches.value_of("target").unwrap(),
         );
     }
     match ret {
         Err(e) => {
-            println!("app error, {}", e);
-            Err(())
+            error!("app error, {}", e);
+            Err(e.exit_code())
         }
         Ok(_) => {
-            println!("done.");
+            info!("done.");
             Ok(())
         }
     }
 }
 
 fn main() {
-    std::process::exit(if cli().is_ok() { 0 } else { 1 });
+    std::process::exit(match cli() {
+        Ok(()) => 0,
+        Err(code) => code,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_all_attempts_every_package() {
+        for jobs in &[1, 3] {
+            let built = Mutex::new(Vec::new());
+            let ret = build_all(&["a", "b", "c", "d"], *jobs, |name| {
+                built.lock().unwrap().push(name.to_string());
+                match name {
+                    "a" | "c" => Err(Error::BuildError()),
+                    _ => Ok(()),
+                }
+            });
+            assert_eq!(built.into_inner().unwrap().len(), 4);
+            match ret {
+                Err(Error::BuildsError(failed)) => assert_eq!(failed, vec!["a", "c"]),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert!(build_all(&["a", "b"], 1, |_| Ok(())).is_ok());
+    }
+
+    fn rebase_matches(args: &[&str]) -> Result<Vec<String>> {
+        let matches = build_cli().get_matches_from(args);
+        let matches = matches.subcommand_matches("rebase").unwrap();
+        rebase_names(matches).map(|names| names.iter().map(|n| n.to_string()).collect())
+    }
+
+    #[test]
+    fn rebase_several_packages() {
+        assert_eq!(
+            rebase_matches(&["uosp", "rebase", "nova", "21.0.0"]).unwrap(),
+            vec!["nova"]
+        );
+        assert_eq!(
+            rebase_matches(&["uosp", "rebase", "nova, glance,", "--latest"]).unwrap(),
+            vec!["nova", "glance"]
+        );
+        match rebase_matches(&["uosp", "rebase", "nova,glance", "21.0.0"]) {
+            Err(Error::OptionError(e)) => assert!(e.starts_with("VERSION")),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn build_missing_checkout() {
+        let opts = BuildOptions {
+            build_area: true,
+            lint: false,
+            builder: None,
+        };
+        let e = build("uosp-missing-package", &opts, false).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            format!(
+                "no checkout of uosp-missing-package found in {}",
+                get_current_dir().join("uosp-missing-package").display()
+            )
+        );
+        assert_eq!(e.exit_code(), 3);
+    }
+
+    #[test]
+    fn apply_patch_sources() {
+        let apply_matches = |args: &[&str]| {
+            build_cli().get_matches_from_safe(args).map(|m| {
+                let m = m.subcommand_matches("apply").unwrap();
+                (
+                    m.value_of("url").map(str::to_string),
+                    m.value_of("file").map(str::to_string),
+                )
+            })
+        };
+        assert_eq!(
+            apply_matches(&["uosp", "apply", "nova", "--file", "fix.patch"]).unwrap(),
+            (None, Some("fix.patch".to_string()))
+        );
+        assert_eq!(
+            apply_matches(&[
+                "uosp",
+                "apply",
+                "nova",
+                "--url",
+                "https://example.com/fix.diff"
+            ])
+            .unwrap(),
+            (Some("https://example.com/fix.diff".to_string()), None)
+        );
+        assert!(apply_matches(&["uosp", "apply", "nova"]).is_err());
+        assert!(apply_matches(&[
+            "uosp",
+            "apply",
+            "nova",
+            "--url",
+            "https://example.com/fix.diff",
+            "--file",
+            "fix.patch"
+        ])
+        .is_err());
+
+        let rootdir = std::env::temp_dir().join(format!("uosp-main-apply-{}", std::process::id()));
+        let workdir = rootdir.join("nova");
+        std::fs::create_dir_all(&workdir).unwrap();
+        let git = |args: &[&str]| {
+            assert!(std::process::Command::new("git")
+                .current_dir(&workdir)
+                .args(["-c", "user.name=uosp", "-c", "user.email=uosp@localhost"])
+                .args(args)
+                .status()
+                .unwrap()
+                .success());
+        };
+        git(&["init", "-q", "-b", "master"]);
+        git(&["commit", "-q", "--allow-empty", "-m", "init"]);
+        let patch = |name: &str| {
+            format!(
+                "diff --git a/{0} b/{0}\nnew file mode 100644\n--- /dev/null\n+++ b/{0}\n\
+                 @@ -0,0 +1 @@\n+fixed\n",
+                name
+            )
+        };
+        std::fs::write(rootdir.join("file.patch"), patch("from-file")).unwrap();
+        std::fs::write(rootdir.join("url.patch"), patch("from-url")).unwrap();
+        let url = format!("file://{}", rootdir.join("url.patch").display());
+        let from_file = apply("nova", &rootdir, "master", None, Some("file.patch"), false);
+        let from_url = apply("nova", &rootdir, "master", Some(&url), None, false);
+        let applied = (
+            workdir.join("from-file").exists(),
+            workdir.join("from-url").exists(),
+        );
+        std::fs::remove_dir_all(&rootdir).unwrap();
+        from_file.unwrap();
+        from_url.unwrap();
+        assert_eq!(applied, (true, true));
+    }
+
+    #[test]
+    fn completions() {
+        for shell in &Shell::variants() {
+            let mut script = Vec::new();
+            build_cli().gen_completions_to(
+                crate_name!(),
+                shell.parse::<Shell>().unwrap(),
+                &mut script,
+            );
+            let script = String::from_utf8(script).unwrap();
+            assert!(script.contains("uosp"), "{}", shell);
+            for subcommand in &["rebase", "publish", "completions"] {
+                assert!(
+                    script.contains(subcommand),
+                    "{} misses {}",
+                    shell,
+                    subcommand
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn publish_ppa_option() {
+        let config = Config::default();
+        let publish_matches = |args: &[&str]| {
+            let matches = build_cli().get_matches_from(args);
+            let matches = matches.subcommand_matches("publish").unwrap();
+            (
+                matches.value_of("ppa").map(str::to_string),
+                publish_series(matches, &config),
+            )
+        };
+        // The only positional after the project is the serie.
+        let (ppa, series) = publish_matches(&["uosp", "publish", "nova", "eoan"]);
+        assert_eq!(ppa, None);
+        assert_eq!(series.unwrap(), ["eoan"]);
+        let (ppa, series) = publish_matches(&[
+            "uosp",
+            "publish",
+            "nova",
+            "--ppa",
+            "ppa:sahid-ferdjaoui/eoan-train",
+            "-s",
+            "disco",
+        ]);
+        assert_eq!(ppa.as_deref(), Some("ppa:sahid-ferdjaoui/eoan-train"));
+        assert_eq!(series.unwrap(), ["disco"]);
+        assert!(config.ppa(None).is_err());
+    }
 }
@@ -0,0 +1,133 @@
+// Copyright 2019 Canonical Ltd. All rights reserved.  Use
+// of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Workflows as an explicit list of actions.
+//!
+//! A workflow is first computed as a `Plan`, which can be printed to
+//! preview what would be done, then each of its actions is executed
+//! on the package.
+
+use std::fmt::{self, Display};
+use std::path::Path;
+
+use changelog::{ChangeLogMessage, Distribution, ReleaseMode};
+use git::GitCloneUrl;
+
+use crate::{Error, ImportOptions, Package, Result};
+
+#[derive(Debug)]
+pub enum Action {
+    Checkout(String),
+    Download(String),
+    Import(String, String),
+    DiffStat(String),
+    NewRelease(String, ChangeLogMessage, Distribution, ReleaseMode),
+    AddEntry(ChangeLogMessage),
+    Commit,
+    /// Tags the release committed, the version is the one expected
+    /// when planning, the tag uses the one of debian/changelog.
+    Tag(String),
+    UpstreamLog(String, String),
+    Show,
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Action::*;
+        match self {
+            Checkout(b) => write!(f, "checkout branch {}", b),
+            Download(v) => write!(f, "download tarball of version {}", v),
+            Import(v, a) => write!(f, "import tarball {} as version {}", a, v),
+            DiffStat(r) => write!(f, "show diff stat of {}", r),
+            NewRelease(v, m, d, r) => {
+                write!(f, "new {:?} {:?} release {} \"{}\"", d, r, v, m)
+            }
+            AddEntry(m) => write!(f, "add changelog entry \"{}\"", m),
+            Commit => write!(f, "commit based on changelog"),
+            Tag(v) => write!(f, "tag release as {}", Package::debian_tag(v)),
+            UpstreamLog(from, to) => write!(
+                f,
+                "append upstream log {} to the commit",
+                Package::upstream_log_range(from, to)
+            ),
+            Show => write!(f, "show last commit"),
+        }
+    }
+}
+
+impl Action {
+    /// Executes the action on `pkg`, which must have been cloned.
+    pub fn execute(&self, pkg: &Package, import: &ImportOptions) -> Result<()> {
+        use self::Action::*;
+        let git = pkg
+            .git()
+            .ok_or_else(|| Error::Fatal(format!("package {} is not cloned", pkg.name())))?;
+        let chg = pkg.changelog();
+        match self {
+            Checkout(b) => git.checkout(b)?,
+            Download(v) => {
+                pkg.download_and_verify(v, import)?;
+            }
+            Import(v, a) => {
+                // The tarball downloaded may not use the compression
+                // planned.
+                let archive = if Path::new(a).exists() {
+                    a.to_string()
+                } else {
+                    pkg.orig_tarball(v).to_string_lossy().to_string()
+                };
+                pkg.apply_tarball(v, &archive, &import.merge)?
+            }
+            DiffStat(r) => println!("{}", git.diff_stat(r)?),
+            NewRelease(v, m, d, r) => chg.new_release(v, m.clone(), *d, *r)?,
+            AddEntry(m) => chg.add_entry(m.clone())?,
+            Commit => git.debcommit()?,
+            Tag(v) => {
+                // The top entry may have been continued rather than
+                // created, nothing has been released in dry-run mode.
+                let head = chg.get_head_full_version();
+                if pkg.dry_run || head.is_empty() {
+                    pkg.tag_release(v)?
+                } else {
+                    pkg.tag_release(&head)?
+                }
+            }
+            UpstreamLog(from, to) => git.amend_message(&pkg.upstream_log(from, to)?)?,
+            Show => git.show()?,
+        }
+        Ok(())
+    }
+}
+
+/// Ordered list of actions to run on a package after to have cloned
+/// it from `url`.
+#[derive(Debug)]
+pub struct Plan {
+    pub name: String,
+    pub url: GitCloneUrl,
+    pub actions: Vec<Action>,
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.url {
+            GitCloneUrl::VCSGit => writeln!(f, "1. clone vcsgit:{}", self.name)?,
+            url => writeln!(f, "1. clone {} from {}", self.name, url)?,
+        }
+        for (i, action) in self.actions.iter().enumerate() {
+            writeln!(f, "{}. {}", i + 2, action)?;
+        }
+        Ok(())
+    }
+}
+
+impl Plan {
+    /// Executes all the actions of the plan on `pkg`.
+    pub fn execute(&self, pkg: &Package, import: &ImportOptions) -> Result<()> {
+        for action in &self.actions {
+            action.execute(pkg, import)?;
+        }
+        Ok(())
+    }
+}
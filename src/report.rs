@@ -0,0 +1,183 @@
+// Copyright 2019 Canonical Ltd. All rights reserved.  Use
+// of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Summary of the packages handled during a run.
+//!
+//! Each package handled produces an `Outcome`, the `Report` renders
+//! them as an aligned table for the terminal, or as CSV/JSON to be
+//! consumed by other tools.
+
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::Result;
+
+/// Result of an operation on a single package.
+#[derive(Debug)]
+pub struct Outcome {
+    pub name: String,
+    pub old_version: Option<String>,
+    pub new_version: String,
+    pub success: bool,
+    pub elapsed: Duration,
+}
+
+impl Outcome {
+    fn fields(&self) -> [String; 5] {
+        [
+            self.name.clone(),
+            self.old_version.clone().unwrap_or_else(|| "-".to_string()),
+            self.new_version.clone(),
+            if self.success { "ok" } else { "failed" }.to_string(),
+            format!("{:.1}s", self.elapsed.as_secs_f64()),
+        ]
+    }
+}
+
+static HEADERS: [&str; 5] = ["package", "old", "new", "status", "elapsed"];
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub outcomes: Vec<Outcome>,
+}
+
+impl Report {
+    pub fn new() -> Report {
+        Report::default()
+    }
+
+    pub fn push(&mut self, outcome: Outcome) {
+        self.outcomes.push(outcome);
+    }
+
+    pub fn to_csv(&self) -> String {
+        let escape = |s: &str| {
+            if s.contains(',') || s.contains('"') || s.contains('\n') {
+                format!("\"{}\"", s.replace('"', "\"\""))
+            } else {
+                s.to_string()
+            }
+        };
+        let mut out = HEADERS.join(",");
+        out.push('\n');
+        for outcome in &self.outcomes {
+            let fields: Vec<String> = outcome.fields().iter().map(|f| escape(f)).collect();
+            out.push_str(&fields.join(","));
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        let escape = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+        let entries: Vec<String> = self
+            .outcomes
+            .iter()
+            .map(|o| {
+                format!(
+                    "{{\"package\":\"{}\",\"old\":{},\"new\":\"{}\",\"success\":{},\"elapsed\":{:.3}}}",
+                    escape(&o.name),
+                    match &o.old_version {
+                        Some(v) => format!("\"{}\"", escape(v)),
+                        None => "null".to_string(),
+                    },
+                    escape(&o.new_version),
+                    o.success,
+                    o.elapsed.as_secs_f64()
+                )
+            })
+            .collect();
+        format!("[{}]\n", entries.join(","))
+    }
+
+    /// Writes the report to `path`, as JSON when the file extension
+    /// is `.json` and as CSV otherwise.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let content = match path.extension() {
+            Some(ext) if ext == "json" => self.to_json(),
+            _ => self.to_csv(),
+        };
+        fs::write(path, content)?;
+        Ok(())
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rows: Vec<[String; 5]> = self.outcomes.iter().map(|o| o.fields()).collect();
+        let mut widths: Vec<usize> = HEADERS.iter().map(|h| h.len()).collect();
+        for row in &rows {
+            for (i, field) in row.iter().enumerate() {
+                widths[i] = widths[i].max(field.len());
+            }
+        }
+        let headers: Vec<String> = HEADERS.iter().map(|h| h.to_string()).collect();
+        for row in std::iter::once(&headers[..]).chain(rows.iter().map(|r| &r[..])) {
+            let line: Vec<String> = row
+                .iter()
+                .enumerate()
+                .map(|(i, field)| format!("{:width$}", field, width = widths[i]))
+                .collect();
+            writeln!(f, "{}", line.join("  ").trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Report {
+        let mut report = Report::new();
+        report.push(Outcome {
+            name: "nova".to_string(),
+            old_version: Some("2:19.0.0-0ubuntu1".to_string()),
+            new_version: "2:19.0.1-0ubuntu1".to_string(),
+            success: true,
+            elapsed: Duration::from_millis(12500),
+        });
+        report.push(Outcome {
+            name: "a,\"b\"".to_string(),
+            old_version: None,
+            new_version: "1.0-1".to_string(),
+            success: false,
+            elapsed: Duration::from_secs(3),
+        });
+        report
+    }
+
+    #[test]
+    fn render_table() {
+        assert_eq!(
+            sample().to_string(),
+            "package  old                new                status  elapsed\n\
+             nova     2:19.0.0-0ubuntu1  2:19.0.1-0ubuntu1  ok      12.5s\n\
+             a,\"b\"    -                  1.0-1              failed  3.0s\n"
+        );
+    }
+
+    #[test]
+    fn render_csv() {
+        assert_eq!(
+            sample().to_csv(),
+            "package,old,new,status,elapsed\n\
+             nova,2:19.0.0-0ubuntu1,2:19.0.1-0ubuntu1,ok,12.5s\n\
+             \"a,\"\"b\"\"\",-,1.0-1,failed,3.0s\n"
+        );
+    }
+
+    #[test]
+    fn render_json() {
+        assert_eq!(
+            sample().to_json(),
+            "[{\"package\":\"nova\",\"old\":\"2:19.0.0-0ubuntu1\",\"new\":\"2:19.0.1-0ubuntu1\",\
+             \"success\":true,\"elapsed\":12.500},\
+             {\"package\":\"a,\\\"b\\\"\",\"old\":null,\"new\":\"1.0-1\",\
+             \"success\":false,\"elapsed\":3.000}]\n"
+        );
+    }
+}
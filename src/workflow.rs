@@ -0,0 +1,1063 @@
+// Copyright 2019 Canonical Ltd. All rights reserved.  Use
+// of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Workflows run by the uosp subcommands on a package.
+//!
+//! Each workflow takes an option struct, so they can be scripted from
+//! another program, e.g. to rebase a set of packages in a row. The
+//! packages are checked out in `rootdir` of the options.
+
+use std::path::PathBuf;
+
+use changelog::{ChangeLogMessage, Distribution, Kind, ReleaseMode, Version};
+use git::{Git, GitCloneUrl};
+use glob::Pattern;
+use log::{debug, error, info, warn};
+
+use crate::plan::{Action, Plan};
+use crate::{Error, ImportOptions, Package, Published, Result};
+
+pub const KIND_OPENSTACK: &str = "openstack";
+pub const KIND_REGULAR: &str = "regular";
+pub const KIND_AUTO: &str = "auto";
+
+/// Resolves `kind` for a package, when `auto` is requested the kind
+/// is detected from the packaging, falling back to regular.
+pub fn resolve_kind<'a>(pkg: &Package, kind: &'a str) -> &'a str {
+    if kind != KIND_AUTO {
+        return kind;
+    }
+    match pkg.is_openstack() {
+        Ok(true) => KIND_OPENSTACK,
+        _ => KIND_REGULAR,
+    }
+}
+
+/// Options of `rebase`.
+#[derive(Debug)]
+pub struct RebaseOptions {
+    pub name: String,
+    /// Upstream version, the latest found by uscan when not set.
+    pub version: Option<String>,
+    pub release: String,
+    pub bugid: Option<String>,
+    /// CVE fixed and its description.
+    pub cve: Option<(String, String)>,
+    /// Security issue fixed without CVE assigned.
+    pub security: Option<String>,
+    pub kind: String,
+    pub dist: String,
+    pub import: ImportOptions,
+    /// Only prints the actions of the rebase.
+    pub plan: bool,
+    pub upstream_log: bool,
+    pub diff_stat: bool,
+    pub tag: bool,
+    /// Only updates the changelog of an existing checkout.
+    pub changelog_only: bool,
+    pub mode: ReleaseMode,
+    pub check_repo: bool,
+    pub new_stanza: bool,
+    /// Name and email signing the changelog entries.
+    pub maintainer: Option<(String, String)>,
+    /// Clone URL, see `GitCloneUrl::from_spec`.
+    pub git_url: Option<String>,
+    pub rootdir: PathBuf,
+    pub dry_run: bool,
+}
+
+/// Returns the current directory, where the packages are checked out
+/// by default.
+fn current_dir() -> PathBuf {
+    std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
+}
+
+impl RebaseOptions {
+    /// Returns the options rebasing `name` on the master release of
+    /// an OpenStack package for Ubuntu, to the latest upstream
+    /// version.
+    ///
+    /// ```
+    /// use uosp::RebaseOptions;
+    ///
+    /// let opts = RebaseOptions::new("nova")
+    ///     .with_version("21.0.0")
+    ///     .with_release("ussuri")
+    ///     .with_bugid("1867654")
+    ///     .with_plan(true);
+    ///
+    /// assert_eq!(opts.kind, "openstack");
+    /// assert_eq!(opts.dist, "ubuntu");
+    /// ```
+    pub fn new(name: &str) -> RebaseOptions {
+        RebaseOptions {
+            name: name.to_string(),
+            version: None,
+            release: "master".to_string(),
+            bugid: None,
+            cve: None,
+            security: None,
+            kind: KIND_OPENSTACK.to_string(),
+            dist: "ubuntu".to_string(),
+            import: ImportOptions::default(),
+            plan: false,
+            upstream_log: false,
+            diff_stat: false,
+            tag: false,
+            changelog_only: false,
+            mode: ReleaseMode::NewUpstream,
+            check_repo: false,
+            new_stanza: false,
+            maintainer: None,
+            git_url: None,
+            rootdir: current_dir(),
+            dry_run: false,
+        }
+    }
+
+    pub fn with_version(mut self, version: &str) -> RebaseOptions {
+        self.version = Some(version.to_string());
+        self
+    }
+
+    pub fn with_release(mut self, release: &str) -> RebaseOptions {
+        self.release = release.to_string();
+        self
+    }
+
+    pub fn with_bugid(mut self, bugid: &str) -> RebaseOptions {
+        self.bugid = Some(bugid.to_string());
+        self
+    }
+
+    pub fn with_cve(mut self, id: &str, desc: &str) -> RebaseOptions {
+        self.cve = Some((id.to_string(), desc.to_string()));
+        self
+    }
+
+    pub fn with_security(mut self, desc: &str) -> RebaseOptions {
+        self.security = Some(desc.to_string());
+        self
+    }
+
+    /// Sets the kind of the package, i.e. openstack, regular or auto.
+    pub fn with_kind(mut self, kind: &str) -> RebaseOptions {
+        self.kind = kind.to_string();
+        self
+    }
+
+    pub fn with_dist(mut self, dist: &str) -> RebaseOptions {
+        self.dist = dist.to_string();
+        self
+    }
+
+    pub fn with_import(mut self, import: ImportOptions) -> RebaseOptions {
+        self.import = import;
+        self
+    }
+
+    pub fn with_plan(mut self, plan: bool) -> RebaseOptions {
+        self.plan = plan;
+        self
+    }
+
+    pub fn with_upstream_log(mut self, upstream_log: bool) -> RebaseOptions {
+        self.upstream_log = upstream_log;
+        self
+    }
+
+    pub fn with_diff_stat(mut self, diff_stat: bool) -> RebaseOptions {
+        self.diff_stat = diff_stat;
+        self
+    }
+
+    pub fn with_tag(mut self, tag: bool) -> RebaseOptions {
+        self.tag = tag;
+        self
+    }
+
+    pub fn with_changelog_only(mut self, changelog_only: bool) -> RebaseOptions {
+        self.changelog_only = changelog_only;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: ReleaseMode) -> RebaseOptions {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_check_repo(mut self, check_repo: bool) -> RebaseOptions {
+        self.check_repo = check_repo;
+        self
+    }
+
+    pub fn with_new_stanza(mut self, new_stanza: bool) -> RebaseOptions {
+        self.new_stanza = new_stanza;
+        self
+    }
+
+    pub fn with_maintainer(mut self, name: &str, email: &str) -> RebaseOptions {
+        self.maintainer = Some((name.to_string(), email.to_string()));
+        self
+    }
+
+    pub fn with_git_url(mut self, git_url: &str) -> RebaseOptions {
+        self.git_url = Some(git_url.to_string());
+        self
+    }
+
+    pub fn with_rootdir(mut self, rootdir: PathBuf) -> RebaseOptions {
+        self.rootdir = rootdir;
+        self
+    }
+
+    pub fn with_dry_run(mut self, dry_run: bool) -> RebaseOptions {
+        self.dry_run = dry_run;
+        self
+    }
+}
+
+/// Returns the hash of the upstream branch of `pkg` before the import,
+/// or the empty tree on a first import since there is nothing to diff
+/// against.
+fn upstream_before_import(pkg: &Package) -> String {
+    let git = Git::open(pkg.workdir().to_path_buf());
+    ["refs/heads/upstream", "refs/remotes/origin/upstream"]
+        .iter()
+        .find_map(|r| git.ref_hash(r).ok().flatten())
+        .unwrap_or_else(|| git::EMPTY_TREE.to_string())
+}
+
+/// Computes the plan of a rebase to `version` once `pkg` cloned from
+/// `url`.
+pub fn rebase_plan(
+    pkg: &Package,
+    opts: &RebaseOptions,
+    version: &str,
+    url: GitCloneUrl,
+) -> Result<Plan> {
+    let kind = resolve_kind(pkg, &opts.kind);
+    let release = opts.release.as_str();
+    let archive = pkg.orig_tarball(version).to_string_lossy().to_string();
+    let mut actions = Vec::new();
+    if !opts.changelog_only {
+        actions.push(Action::Checkout("pristine-tar".to_string()));
+        actions.push(Action::Checkout("upstream".to_string()));
+    }
+    actions.push(Action::Checkout(Package::format_branch(release)));
+    if !opts.changelog_only {
+        // The tarball of a snapshot is generated by `rebase` before
+        // planning, see Package::acquire_tarball.
+        if !Package::is_snapshot_version(version) {
+            actions.push(Action::Download(version.to_string()));
+        }
+        actions.push(Action::Import(version.to_string(), archive));
+        if opts.diff_stat {
+            actions.push(Action::DiffStat(format!(
+                "{}..upstream",
+                upstream_before_import(pkg)
+            )));
+        }
+    }
+    actions.push(Action::NewRelease(
+        version.to_string(),
+        ChangeLogMessage::for_release(
+            Kind::from(kind),
+            Distribution::from(opts.dist.as_str()),
+            release,
+            version,
+            opts.bugid.as_deref(),
+        ),
+        Distribution::from(opts.dist.as_str()),
+        opts.mode,
+    ));
+    if let Some((id, desc)) = &opts.cve {
+        actions.push(Action::AddEntry(ChangeLogMessage::CVEFix(
+            desc.to_string(),
+            id.to_string(),
+        )));
+    }
+    if let Some(desc) = &opts.security {
+        actions.push(Action::AddEntry(ChangeLogMessage::SecurityFix(
+            desc.to_string(),
+        )));
+    }
+    actions.push(Action::Commit);
+    if opts.upstream_log {
+        let head = pkg.changelog().get_head_full_version();
+        match head.parse::<Version>() {
+            Ok(previous) => actions.push(Action::UpstreamLog(
+                previous.upstream().to_string(),
+                version.to_string(),
+            )),
+            Err(_) => warn!("No previous version found, skipping upstream log."),
+        }
+    }
+    // Last to change the history, the upstream log amends the commit.
+    if opts.tag {
+        actions.push(Action::Tag(pkg.changelog().release_version(
+            version,
+            Distribution::from(opts.dist.as_str()),
+            opts.mode,
+        )?));
+    }
+    actions.push(Action::Show);
+    Ok(Plan {
+        name: pkg.name().to_string(),
+        url,
+        actions,
+    })
+}
+
+/// Rebases a package to a new upstream version
+///
+/// The process is to create point release based on the last version
+/// published upstream. Running this command will clone project name
+/// using package VCS (see: --git-url <URL> if VCS not right). Using
+/// uscan to download tarball of the proposed version and import it
+/// using gbpimport-orig. Finally update the d/changelog and commit
+/// the all in git repo.
+///
+/// When `plan` is set, the actions are only printed. When `version`
+/// is not provided the latest upstream version found by uscan is
+/// used. A pre-release or snapshot `version` is rebased on a snapshot
+/// generated from upstream. The package revision is reset for a new
+/// upstream `mode`, and continued for merges and rebuilds. Returns
+/// the version rebased on.
+pub fn rebase(opts: &RebaseOptions) -> Result<String> {
+    let name = opts.name.as_str();
+    if opts.changelog_only && !Package::new(name, opts.rootdir.clone())?.exists() {
+        return Err(Error::Fatal(format!(
+            "--changelog-only requires an existing checkout of {}",
+            name
+        )));
+    }
+    let url = match &opts.git_url {
+        Some(spec) => GitCloneUrl::from_spec(spec)
+            .ok_or_else(|| Error::OptionError(format!("invalid git URL {}", spec)))?,
+        None => Package::clone_url(name, &opts.kind, &opts.dist),
+    };
+    let mut pkg = if opts.plan {
+        Package::new(name, opts.rootdir.clone())?
+    } else {
+        Package::clone_from_url(name, opts.rootdir.clone(), url.clone(), opts.dry_run)?
+    };
+    if let Some((name, email)) = &opts.maintainer {
+        pkg = pkg.with_maintainer(name, email);
+    }
+    pkg.set_new_stanza(opts.new_stanza);
+    let version = match &opts.version {
+        Some(version) => version.to_string(),
+        None => pkg.latest_upstream_version()?,
+    };
+    if opts.plan {
+        if !opts.changelog_only && Package::is_snapshot_version(&version) {
+            info!(
+                "A snapshot tarball of {} would be generated first.",
+                version
+            );
+        }
+        print!("{}", rebase_plan(&pkg, opts, &version, url)?);
+        return Ok(version);
+    }
+
+    info!(
+        "Rebasing {} {} to new upstream version '{}'...",
+        name, opts.release, version
+    );
+
+    let git = pkg.git().unwrap();
+    if opts.check_repo {
+        git.fsck()?;
+    }
+    if !git.is_clean()? {
+        return Err(Error::DirtyError(pkg.workdir().display().to_string()));
+    }
+
+    // uscan is not able to download snapshots, their tarball is
+    // generated first and the package is rebased on the version of
+    // the snapshot.
+    let version = if !opts.changelog_only && Package::is_snapshot_version(&version) {
+        pkg.acquire_tarball(&opts.release, &version, None)?.0
+    } else {
+        version
+    };
+
+    // Used to restore the branches and the tag changed if the rebase
+    // fails, the packaging branch first so it is the one checked out.
+    let branch = Package::format_branch(&opts.release);
+    let mut refs = vec![format!("refs/heads/{}", branch)];
+    if !opts.changelog_only {
+        refs.push("refs/heads/upstream".to_string());
+        refs.push("refs/heads/pristine-tar".to_string());
+        refs.push(format!("refs/tags/{}", Package::upstream_tag(&version)));
+    }
+    let mut original = Vec::new();
+    for refname in refs {
+        let hash = git.ref_hash(&refname)?;
+        original.push((refname, hash));
+    }
+    let plan = rebase_plan(&pkg, opts, &version, url)?;
+    if let Err(e) = plan.execute(&pkg, &opts.import) {
+        warn!("Rebase failed, restoring {}...", name);
+        restore_refs(git, &branch, &original);
+        return Err(e);
+    }
+    Ok(version)
+}
+
+/// Restores the references to the hashes recorded, the ones which did
+/// not exist are deleted. Failures are only logged so the error of the
+/// rebase is the one reported.
+fn restore_refs(git: &Git, branch: &str, original: &[(String, Option<String>)]) {
+    let packaging = format!("refs/heads/{}", branch);
+    for (refname, hash) in original {
+        if git.ref_hash(refname).unwrap_or(None) == *hash {
+            continue;
+        }
+        let checked_out = git
+            .current_branch()
+            .map(|b| format!("refs/heads/{}", b) == *refname)
+            .unwrap_or(false);
+        let restored = match hash {
+            Some(hash) if *refname == packaging => {
+                git.checkout(branch).and_then(|_| git.reset_hard(hash))
+            }
+            // The working tree would not follow the reference.
+            _ if checked_out => {
+                warn!("Unable to restore {}, it is checked out.", refname);
+                continue;
+            }
+            Some(hash) => git.update_ref(refname, hash),
+            None => git.delete_ref(refname),
+        };
+        match restored {
+            Ok(()) => info!("Restored {}.", refname),
+            Err(e) => warn!("Unable to restore {}, {}.", refname, e),
+        }
+    }
+}
+
+/// Options of `snapshot`.
+#[derive(Debug)]
+pub struct SnapshotOptions {
+    pub name: String,
+    /// Next upstream version (e.g. 19.0.1~b1).
+    pub version: String,
+    /// Upstream project when its name differs from the package one.
+    pub upstream: Option<String>,
+    /// Upstream commit or tag, the HEAD of master when not set.
+    pub rev: Option<String>,
+    pub import: ImportOptions,
+    pub new_stanza: bool,
+    /// Name and email signing the changelog entry.
+    pub maintainer: Option<(String, String)>,
+    pub rootdir: PathBuf,
+    pub dry_run: bool,
+}
+
+impl SnapshotOptions {
+    /// Returns the options updating `name` to a snapshot of the HEAD
+    /// of its upstream master branch, versioned after `version`.
+    ///
+    /// ```
+    /// use uosp::SnapshotOptions;
+    ///
+    /// let opts = SnapshotOptions::new("python-oslo.config", "8.0.0~b1")
+    ///     .with_upstream("oslo.config")
+    ///     .with_rev("8.0.0.0b1");
+    ///
+    /// assert_eq!(opts.rev.as_deref(), Some("8.0.0.0b1"));
+    /// ```
+    pub fn new(name: &str, version: &str) -> SnapshotOptions {
+        SnapshotOptions {
+            name: name.to_string(),
+            version: version.to_string(),
+            upstream: None,
+            rev: None,
+            import: ImportOptions::default(),
+            new_stanza: false,
+            maintainer: None,
+            rootdir: current_dir(),
+            dry_run: false,
+        }
+    }
+
+    pub fn with_upstream(mut self, upstream: &str) -> SnapshotOptions {
+        self.upstream = Some(upstream.to_string());
+        self
+    }
+
+    pub fn with_rev(mut self, rev: &str) -> SnapshotOptions {
+        self.rev = Some(rev.to_string());
+        self
+    }
+
+    pub fn with_import(mut self, import: ImportOptions) -> SnapshotOptions {
+        self.import = import;
+        self
+    }
+
+    pub fn with_new_stanza(mut self, new_stanza: bool) -> SnapshotOptions {
+        self.new_stanza = new_stanza;
+        self
+    }
+
+    pub fn with_maintainer(mut self, name: &str, email: &str) -> SnapshotOptions {
+        self.maintainer = Some((name.to_string(), email.to_string()));
+        self
+    }
+
+    pub fn with_rootdir(mut self, rootdir: PathBuf) -> SnapshotOptions {
+        self.rootdir = rootdir;
+        self
+    }
+
+    pub fn with_dry_run(mut self, dry_run: bool) -> SnapshotOptions {
+        self.dry_run = dry_run;
+        self
+    }
+}
+
+/// Creates snapshot of an upstream source and rebase the package with it.
+pub fn snapshot(opts: &SnapshotOptions) -> Result<()> {
+    let name = opts.name.as_str();
+    let version = opts.version.as_str();
+    let upstream = opts.upstream.as_deref();
+    let import = &opts.import;
+    info!("Updating package {} to a new upstream snapshot...", name);
+
+    let release = "master";
+    let branch = Package::format_branch(release);
+    let mut pkg = Package::clone(
+        name,
+        opts.rootdir.clone(),
+        KIND_OPENSTACK,
+        "ubuntu",
+        opts.dry_run,
+    )?;
+    if let Some((name, email)) = &opts.maintainer {
+        pkg = pkg.with_maintainer(name, email);
+    }
+    pkg.set_new_stanza(opts.new_stanza);
+
+    let git = pkg.git().unwrap();
+    git.checkout("pristine-tar")?;
+    git.checkout("upstream")?;
+    git.checkout(&branch)?;
+
+    let (gitversion, archive) =
+        pkg.snapshot_tarball(release, version, upstream, opts.rev.as_deref())?;
+    let archive = pkg.recompress_tarball(&archive, import.compression)?;
+    pkg.apply_tarball(&gitversion, &archive.to_string_lossy(), &import.merge)?;
+
+    let msg = ChangeLogMessage::OSNewUpstreamSnapshot(ChangeLogMessage::release_name(release));
+    let chg = pkg.changelog();
+    chg.new_release(
+        &gitversion,
+        msg,
+        Distribution::Ubuntu,
+        ReleaseMode::NewUpstream,
+    )?;
+
+    git.debcommit()?;
+    git.show()?;
+
+    // Wanning that the process is not yet finished.
+    // TODO(sahid): implement some sort of magic to handle deps.
+    warn!("Please consider to check (build-)deps.");
+
+    Ok(())
+}
+
+/// Options of `clone`.
+#[derive(Debug)]
+pub struct CloneOptions {
+    pub name: String,
+    /// Branches not checked out.
+    pub exclude: Vec<Pattern>,
+    /// Skips the missing optional branches (i.e. pristine-tar and
+    /// upstream).
+    pub continue_on_missing: bool,
+    pub rootdir: PathBuf,
+    pub dry_run: bool,
+}
+
+/// Indicates whether `branch` matches one of the glob `patterns`.
+fn is_excluded(branch: &str, patterns: &[Pattern]) -> bool {
+    patterns.iter().any(|p| p.matches(branch))
+}
+
+/// Clones package and checks out its branches.
+pub fn clone(opts: &CloneOptions) -> Result<()> {
+    info!("Cloning package '{}'...", opts.name);
+
+    let pkg = Package::clone(
+        &opts.name,
+        opts.rootdir.clone(),
+        KIND_OPENSTACK,
+        "ubuntu",
+        opts.dry_run,
+    )?;
+
+    let git = pkg.git().unwrap();
+    for branch in &["pristine-tar", "upstream", "master"] {
+        if is_excluded(branch, &opts.exclude) {
+            debug!("Skipping excluded branch '{}'.", branch);
+            continue;
+        }
+        let has_remote = git.remote_branch_exists(branch)?;
+        if !git.branch_exists(branch)? && !has_remote {
+            if !opts.continue_on_missing || *branch == "master" {
+                return Err(git::Error::CheckoutError(branch.to_string()).into());
+            }
+            warn!("Skipping missing branch '{}'.", branch);
+            continue;
+        }
+        git.checkout(branch)?;
+        if has_remote {
+            git.set_upstream(branch, &format!("origin/{}", branch))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Options of `upstream`.
+#[derive(Debug)]
+pub struct UpstreamOptions {
+    pub name: String,
+    /// New upstream version (e.g. 19.0.1).
+    pub version: String,
+    pub release: String,
+    // TODO(sahid): the bug is not referenced by the changelog entry
+    // yet.
+    pub bugid: Option<String>,
+    pub kind: String,
+    pub dist: String,
+    pub import: ImportOptions,
+    pub new_stanza: bool,
+    /// Name and email signing the changelog entry.
+    pub maintainer: Option<(String, String)>,
+    pub rootdir: PathBuf,
+    pub dry_run: bool,
+}
+
+impl UpstreamOptions {
+    /// Returns the options updating the master release of the
+    /// OpenStack package `name` for Ubuntu to the upstream `version`.
+    ///
+    /// ```
+    /// use uosp::UpstreamOptions;
+    ///
+    /// let opts = UpstreamOptions::new("nova", "21.0.0")
+    ///     .with_release("ussuri")
+    ///     .with_kind("regular");
+    ///
+    /// assert_eq!(opts.release, "ussuri");
+    /// assert_eq!(opts.dist, "ubuntu");
+    /// ```
+    pub fn new(name: &str, version: &str) -> UpstreamOptions {
+        UpstreamOptions {
+            name: name.to_string(),
+            version: version.to_string(),
+            release: "master".to_string(),
+            bugid: None,
+            kind: KIND_OPENSTACK.to_string(),
+            dist: "ubuntu".to_string(),
+            import: ImportOptions::default(),
+            new_stanza: false,
+            maintainer: None,
+            rootdir: current_dir(),
+            dry_run: false,
+        }
+    }
+
+    pub fn with_release(mut self, release: &str) -> UpstreamOptions {
+        self.release = release.to_string();
+        self
+    }
+
+    pub fn with_bugid(mut self, bugid: &str) -> UpstreamOptions {
+        self.bugid = Some(bugid.to_string());
+        self
+    }
+
+    pub fn with_kind(mut self, kind: &str) -> UpstreamOptions {
+        self.kind = kind.to_string();
+        self
+    }
+
+    pub fn with_dist(mut self, dist: &str) -> UpstreamOptions {
+        self.dist = dist.to_string();
+        self
+    }
+
+    pub fn with_import(mut self, import: ImportOptions) -> UpstreamOptions {
+        self.import = import;
+        self
+    }
+
+    pub fn with_new_stanza(mut self, new_stanza: bool) -> UpstreamOptions {
+        self.new_stanza = new_stanza;
+        self
+    }
+
+    pub fn with_maintainer(mut self, name: &str, email: &str) -> UpstreamOptions {
+        self.maintainer = Some((name.to_string(), email.to_string()));
+        self
+    }
+
+    pub fn with_rootdir(mut self, rootdir: PathBuf) -> UpstreamOptions {
+        self.rootdir = rootdir;
+        self
+    }
+
+    pub fn with_dry_run(mut self, dry_run: bool) -> UpstreamOptions {
+        self.dry_run = dry_run;
+        self
+    }
+}
+
+// TODO(sahid): should be merged with rebase, the diff si only in the
+// change log message.
+/// Imports the upstream `version` in the package and opens a new
+/// changelog entry for it.
+pub fn upstream(opts: &UpstreamOptions) -> Result<()> {
+    let name = opts.name.as_str();
+    let version = opts.version.as_str();
+    let release = opts.release.as_str();
+    let dist = opts.dist.as_str();
+    let import = &opts.import;
+    info!(
+        "Rebasing {} {} to new upstream version '{}'...",
+        name, release, version
+    );
+
+    let branch = Package::format_branch(release);
+
+    let mut pkg = Package::clone(name, opts.rootdir.clone(), &opts.kind, dist, opts.dry_run)?;
+    if let Some((name, email)) = &opts.maintainer {
+        pkg = pkg.with_maintainer(name, email);
+    }
+    pkg.set_new_stanza(opts.new_stanza);
+    let kind = resolve_kind(&pkg, &opts.kind);
+
+    let git = pkg.git().unwrap();
+    git.checkout("pristine-tar")?;
+    git.checkout("upstream")?;
+    git.checkout(&branch)?;
+
+    let archive = pkg.download_and_verify(version, import)?;
+    pkg.apply_tarball(version, &archive.to_string_lossy(), &import.merge)?;
+
+    let chg = pkg.changelog();
+    let msg = if kind == KIND_OPENSTACK {
+        ChangeLogMessage::OSNewUpstreamSnapshot(ChangeLogMessage::release_name(release))
+    } else {
+        // Assumes KIND_REGULAR
+        ChangeLogMessage::NewUpstreamRelease(version.to_string())
+    };
+    chg.new_release(
+        version,
+        msg,
+        Distribution::from(dist),
+        ReleaseMode::NewUpstream,
+    )?;
+
+    git.debcommit()?;
+    git.show()?;
+
+    Ok(())
+}
+
+/// Options of `publish`.
+#[derive(Debug)]
+pub struct PublishOptions {
+    pub name: String,
+    pub ppa: String,
+    /// Ubuntu series the package is backported to, in order.
+    pub series: Vec<String>,
+    /// dput host used instead of the ppa.
+    pub dput_host: Option<String>,
+    /// Time of the backport, see `Package::publish`.
+    pub fake_time: Option<String>,
+    /// Builds the package before to publish it.
+    pub build: bool,
+    pub build_area: bool,
+    pub rootdir: PathBuf,
+    pub dry_run: bool,
+}
+
+/// Builds the package when requested, then backports it to the
+/// ppa for each of the series. A failing serie does not stop the next
+/// ones, the result of each serie is returned in order.
+pub fn publish(opts: &PublishOptions) -> Result<Vec<(String, Result<Published>)>> {
+    let dput_host = opts.dput_host.as_deref();
+    let fake_time = opts.fake_time.as_deref();
+    info!(
+        "Backport {} to '{}', ubuntu {}, fake-time: {:?}...",
+        opts.name,
+        dput_host.unwrap_or(&opts.ppa),
+        opts.series.join(", "),
+        fake_time
+    );
+
+    let mut pkg = Package::clone(
+        &opts.name,
+        opts.rootdir.clone(),
+        KIND_OPENSTACK,
+        "ubuntu",
+        opts.dry_run,
+    )?;
+    pkg.set_build_area(opts.build_area);
+    if !pkg.changelog().is_released() {
+        warn!("The top changelog entry is UNRELEASED, please consider to finalize it.");
+    }
+    if opts.build {
+        pkg.build()?;
+    }
+    let mut results = Vec::new();
+    for serie in &opts.series {
+        let published = pkg.publish(&opts.ppa, serie, dput_host, fake_time);
+        match &published {
+            Ok(published) => {
+                for path in published.dsc.iter().chain(published.changes.iter()) {
+                    info!("Source package produced: {}", path.display());
+                }
+            }
+            Err(e) => error!("Backport to {} failed, {}", serie, e),
+        }
+        results.push((serie.to_string(), published));
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn actions(plan: &Plan) -> Vec<String> {
+        plan.actions.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn rebase_plan_actions() {
+        let pkg = Package::new("nova", PathBuf::from("/nonexistent")).unwrap();
+        let opts = RebaseOptions::new("nova")
+            .with_release("stein")
+            .with_bugid("1867654");
+        let plan = rebase_plan(&pkg, &opts, "19.0.1", GitCloneUrl::VCSGit).unwrap();
+        assert_eq!(plan.name, "nova");
+        assert_eq!(
+            actions(&plan),
+            [
+                "checkout branch pristine-tar",
+                "checkout branch upstream",
+                "checkout branch stable/stein",
+                "download tarball of version 19.0.1",
+                "import tarball /nonexistent/nova_19.0.1.orig.tar.gz as version 19.0.1",
+                "new Ubuntu NewUpstream release 19.0.1 \
+                 \"New stable point release for OpenStack Stein (LP: #1867654).\"",
+                "commit based on changelog",
+                "show last commit",
+            ]
+        );
+    }
+
+    #[test]
+    fn rebase_plan_actions_with_options() {
+        let rootdir =
+            std::env::temp_dir().join(format!("uosp-workflow-plan-{}", std::process::id()));
+        std::fs::create_dir_all(rootdir.join("nova/debian")).unwrap();
+        std::fs::write(
+            rootdir.join("nova/debian/changelog"),
+            "nova (2:19.0.0-0ubuntu1) eoan; urgency=medium\n\n  \
+             * New stable point release for OpenStack Stein.\n\n \
+             -- Sahid Orentino Ferdjaoui <sahid.ferdjaoui@canonical.com>  \
+             Mon, 17 Jun 2019 15:30:00 +0000\n",
+        )
+        .unwrap();
+        let pkg = Package::new("nova", rootdir.clone()).unwrap();
+        let opts = RebaseOptions::new("nova")
+            .with_release("stein")
+            .with_cve("CVE-2019-14433", "arbitrary file read in console")
+            .with_security("token leak in logs")
+            .with_diff_stat(true)
+            .with_upstream_log(true)
+            .with_tag(true);
+        let plan = rebase_plan(&pkg, &opts, "19.0.1", GitCloneUrl::VCSGit);
+        std::fs::remove_dir_all(&rootdir).unwrap();
+        let plan = plan.unwrap();
+        assert_eq!(
+            actions(&plan)[4..],
+            [
+                format!(
+                    "import tarball {} as version 19.0.1",
+                    rootdir.join("nova_19.0.1.orig.tar.gz").display()
+                ),
+                format!("show diff stat of {}..upstream", git::EMPTY_TREE),
+                "new Ubuntu NewUpstream release 19.0.1 \
+                 \"New stable point release for OpenStack Stein.\""
+                    .to_string(),
+                "add changelog entry \
+                 \"Fix arbitrary file read in console (CVE-2019-14433).\""
+                    .to_string(),
+                "add changelog entry \"SECURITY: fix token leak in logs.\"".to_string(),
+                "commit based on changelog".to_string(),
+                "append upstream log 19.0.0..19.0.1 to the commit".to_string(),
+                "tag release as debian/2%19.0.1-0ubuntu1".to_string(),
+                "show last commit".to_string(),
+            ]
+        );
+    }
+
+    fn git(dir: &std::path::Path, args: &[&str]) {
+        let status = std::process::Command::new("git")
+            .current_dir(dir)
+            .args(["-c", "user.name=uosp", "-c", "user.email=uosp@localhost"])
+            .args(args)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    /// Returns a rootdir with a checkout of nova cloned from a
+    /// repository having `branches` besides master.
+    fn checkout(name: &str, branches: &[&str]) -> PathBuf {
+        let rootdir =
+            std::env::temp_dir().join(format!("uosp-workflow-{}-{}", name, std::process::id()));
+        let origin = rootdir.join("origin");
+        std::fs::create_dir_all(&origin).unwrap();
+        git(&origin, &["init", "-q", "-b", "master"]);
+        git(&origin, &["commit", "-q", "--allow-empty", "-m", "init"]);
+        for branch in branches {
+            git(&origin, &["branch", branch]);
+        }
+        git(&rootdir, &["clone", "-q", "origin", "nova"]);
+        rootdir
+    }
+
+    fn clone_options(rootdir: &std::path::Path) -> CloneOptions {
+        CloneOptions {
+            name: "nova".to_string(),
+            exclude: Vec::new(),
+            continue_on_missing: false,
+            rootdir: rootdir.to_path_buf(),
+            dry_run: false,
+        }
+    }
+
+    #[test]
+    fn is_excluded() {
+        let patterns = [Pattern::new("pristine-*").unwrap()];
+        assert!(super::is_excluded("pristine-tar", &patterns));
+        assert!(!super::is_excluded("upstream", &patterns));
+        assert!(!super::is_excluded("pristine-tar", &[]));
+    }
+
+    #[test]
+    fn clone_skips_excluded_branches() {
+        let rootdir = checkout("clone-exclude", &["pristine-tar", "upstream"]);
+        let mut opts = clone_options(&rootdir);
+        opts.exclude = vec![Pattern::new("pristine-*").unwrap()];
+        let cloned = clone(&opts);
+        let git = Git::open(rootdir.join("nova"));
+        let pristine = git.branch_exists("pristine-tar");
+        let upstream = git.branch_exists("upstream");
+        std::fs::remove_dir_all(&rootdir).unwrap();
+        cloned.unwrap();
+        assert!(!pristine.unwrap());
+        assert!(upstream.unwrap());
+    }
+
+    #[test]
+    fn clone_continue_on_missing() {
+        // Neither pristine-tar nor upstream.
+        let rootdir = checkout("clone-missing", &[]);
+        let mut opts = clone_options(&rootdir);
+        let failed = clone(&opts);
+        opts.continue_on_missing = true;
+        let cloned = clone(&opts);
+        std::fs::remove_dir_all(&rootdir).unwrap();
+        match failed {
+            Err(Error::GitError(git::Error::CheckoutError(b))) => assert_eq!(b, "pristine-tar"),
+            other => panic!("unexpected {:?}", other),
+        }
+        cloned.unwrap();
+
+        // Only upstream, which is still checked out.
+        let rootdir = checkout("clone-partial", &["upstream"]);
+        let mut opts = clone_options(&rootdir);
+        opts.continue_on_missing = true;
+        let cloned = clone(&opts);
+        let upstream = Git::open(rootdir.join("nova")).branch_exists("upstream");
+        std::fs::remove_dir_all(&rootdir).unwrap();
+        cloned.unwrap();
+        assert!(upstream.unwrap());
+    }
+
+    #[test]
+    fn clone_requires_master() {
+        let rootdir = checkout("clone-master", &["pristine-tar", "upstream"]);
+        let nova = rootdir.join("nova");
+        git(&nova, &["checkout", "-q", "--detach"]);
+        git(&nova, &["branch", "-q", "-D", "master"]);
+        git(&nova, &["branch", "-q", "-d", "-r", "origin/master"]);
+        let mut opts = clone_options(&rootdir);
+        opts.continue_on_missing = true;
+        let cloned = clone(&opts);
+        std::fs::remove_dir_all(&rootdir).unwrap();
+        match cloned {
+            Err(Error::GitError(git::Error::CheckoutError(b))) => assert_eq!(b, "master"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rebase_plan_diff_stat() {
+        let rootdir = checkout("plan-diff-stat", &["upstream"]);
+        let git = Git::open(rootdir.join("nova"));
+        let upstream = git
+            .ref_hash("refs/remotes/origin/upstream")
+            .unwrap()
+            .unwrap();
+        let pkg = Package::new("nova", rootdir.clone()).unwrap();
+        let opts = RebaseOptions::new("nova").with_diff_stat(true);
+        let plan = rebase_plan(&pkg, &opts, "19.0.1", GitCloneUrl::VCSGit);
+        std::fs::remove_dir_all(&rootdir).unwrap();
+        // Planned before the checkout of upstream.
+        assert_eq!(
+            actions(&plan.unwrap())[5],
+            format!("show diff stat of {}..upstream", upstream)
+        );
+    }
+
+    #[test]
+    fn rebase_plan_changelog_only() {
+        let pkg = Package::new("nova", PathBuf::from("/nonexistent")).unwrap();
+        let opts = RebaseOptions::new("nova")
+            .with_release("stein")
+            .with_changelog_only(true)
+            .with_diff_stat(true);
+        let plan = rebase_plan(&pkg, &opts, "19.0.1", GitCloneUrl::VCSGit).unwrap();
+        assert_eq!(
+            actions(&plan),
+            [
+                "checkout branch stable/stein",
+                "new Ubuntu NewUpstream release 19.0.1 \
+                 \"New stable point release for OpenStack Stein.\"",
+                "commit based on changelog",
+                "show last commit",
+            ]
+        );
+
+        // Nothing to update without a checkout.
+        let opts = opts
+            .with_version("19.0.1")
+            .with_rootdir(PathBuf::from("/nonexistent"));
+        assert!(matches!(rebase(&opts), Err(Error::Fatal(_))));
+    }
+}